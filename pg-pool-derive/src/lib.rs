@@ -0,0 +1,226 @@
+//! `#[derive(FromRow)]`, `#[derive(NamedParams)]` and `embed_migrations!` for pg_pool.
+//!
+//! Field attributes, under `#[pg(...)]`:
+//! - `rename = "col"`: column or `:name` to use instead of the field name
+//! - `default`: use `Default::default()` when the column is missing or NULL (FromRow)
+//! - `flatten`: read the field from the same row or params with its own impl
+//! - `try_from = "Type"`: read the column as `Type` and convert with `TryFrom` (FromRow)
+//! - `skip`: not bound to any `:name` (NamedParams)
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use std::path::PathBuf;
+use syn::{
+    Data, DeriveInput, Field, Fields, LitStr, Type, parse_macro_input,
+    punctuated::Punctuated, spanned::Spanned, token::Comma,
+};
+
+#[path = "../../pg-pool/src/migrate/file_name.rs"]
+mod file_name;
+
+#[proc_macro_derive(FromRow, attributes(pg))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(NamedParams, attributes(pg))]
+pub fn derive_named_params(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_params(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Embeds `<version>_<name>.sql` and `<version>_<name>.down.sql` files from a
+/// directory relative to the calling crate as a `Vec<pg_pool::migrate::Migration>`.
+/// Files are read at compile time; touch the calling source after adding one.
+#[proc_macro]
+pub fn embed_migrations(input: TokenStream) -> TokenStream {
+    let dir = parse_macro_input!(input as LitStr);
+    expand_migrations(&dir)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn named_fields<'a>(input: &'a DeriveInput, derive: &str) -> syn::Result<&'a Punctuated<Field, Comma>> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => Ok(&fields.named),
+            _ => Err(syn::Error::new(input.span(), format!("{derive} requires a struct with named fields"))),
+        },
+        _ => Err(syn::Error::new(input.span(), format!("{derive} can only be derived for structs"))),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input, "FromRow")?;
+    let name = &input.ident;
+    let values = fields.iter()
+        .map(|f| field_value(name, f))
+        .collect::<syn::Result<Vec<_>>>()?;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::pg_pool::FromRow for #name #ty_generics #where_clause {
+            fn from_row(row: &::pg_pool::Row) -> ::std::result::Result<Self, ::pg_pool::PgPoolError> {
+                ::std::result::Result::Ok(Self { #(#values),* })
+            }
+        }
+    })
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    rename: Option<String>,
+    default: bool,
+    flatten: bool,
+    try_from: Option<Type>,
+    skip: bool,
+}
+
+fn field_attrs(field: &Field) -> syn::Result<FieldAttrs> {
+    let mut attrs = FieldAttrs::default();
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("pg")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                attrs.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+            } else if meta.path.is_ident("default") {
+                attrs.default = true;
+            } else if meta.path.is_ident("flatten") {
+                attrs.flatten = true;
+            } else if meta.path.is_ident("skip") {
+                attrs.skip = true;
+            } else if meta.path.is_ident("try_from") {
+                attrs.try_from = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+            } else {
+                return Err(meta.error("unknown pg attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    if attrs.flatten && (attrs.rename.is_some() || attrs.default || attrs.try_from.is_some() || attrs.skip) {
+        return Err(syn::Error::new(field.span(), "flatten cannot be combined with other pg attributes"));
+    }
+    Ok(attrs)
+}
+
+fn field_value(name: &syn::Ident, field: &Field) -> syn::Result<TokenStream2> {
+    let attrs = field_attrs(field)?;
+    let ident = field.ident.as_ref().expect("named field");
+    let ty = &field.ty;
+    if attrs.skip {
+        return Err(syn::Error::new(field.span(), "skip is not supported by FromRow"));
+    }
+
+    if attrs.flatten {
+        return Ok(quote! { #ident: <#ty as ::pg_pool::FromRow>::from_row(row)? });
+    }
+
+    let label = format!("{name}.{}", unraw(ident));
+    let column = attrs.rename.unwrap_or_else(|| unraw(ident));
+    let private = quote! { ::pg_pool::__private };
+
+    let value = match (attrs.try_from, attrs.default) {
+        (None, false) => quote! {
+            #private::get::<#ty>(row, #label, #column)?
+        },
+        (None, true) => quote! {
+            #private::get_opt::<#ty>(row, #label, #column)?.unwrap_or_default()
+        },
+        (Some(src), false) => quote! {
+            #private::convert::<#src, #ty>(#private::get::<#src>(row, #label, #column)?, #label, #column)?
+        },
+        (Some(src), true) => quote! {
+            match #private::get_opt::<#src>(row, #label, #column)? {
+                ::std::option::Option::Some(v) => #private::convert::<#src, #ty>(v, #label, #column)?,
+                ::std::option::Option::None => ::std::default::Default::default(),
+            }
+        },
+    };
+    Ok(quote! { #ident: #value })
+}
+
+fn expand_params(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = named_fields(input, "NamedParams")?;
+    let mut arms = Vec::new();
+    let mut flattened = Vec::new();
+    for field in fields {
+        let attrs = field_attrs(field)?;
+        let ident = field.ident.as_ref().expect("named field");
+        if attrs.default || attrs.try_from.is_some() {
+            return Err(syn::Error::new(field.span(), "default and try_from are only supported by FromRow"));
+        }
+        if attrs.skip {
+            continue;
+        }
+        if attrs.flatten {
+            flattened.push(quote! {
+                if let ::std::option::Option::Some(v) = ::pg_pool::NamedParams::param(&self.#ident, name) {
+                    return ::std::option::Option::Some(v);
+                }
+            });
+            continue;
+        }
+        let param = attrs.rename.unwrap_or_else(|| unraw(ident));
+        arms.push(quote! { #param => ::std::option::Option::Some(&self.#ident), });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::pg_pool::NamedParams for #name #ty_generics #where_clause {
+            fn param(&self, name: &str) -> ::std::option::Option<&(dyn ::pg_pool::__private::ToSql + ::std::marker::Sync)> {
+                match name {
+                    #(#arms)*
+                    _ => {
+                        #(#flattened)*
+                        ::std::option::Option::None
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn unraw(ident: &syn::Ident) -> String {
+    ident.to_string().trim_start_matches("r#").to_string()
+}
+
+fn expand_migrations(dir: &LitStr) -> syn::Result<TokenStream2> {
+    let root = std::env::var("CARGO_MANIFEST_DIR").map(PathBuf::from).unwrap_or_default();
+    let path = root.join(dir.value());
+    let entries = std::fs::read_dir(&path)
+        .map_err(|e| syn::Error::new(dir.span(), format!("{}: {e}", path.display())))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let file = entry.map_err(|e| syn::Error::new(dir.span(), e))?.path();
+        let Some(name) = file.file_name().and_then(|f| f.to_str()) else { continue };
+        let Some((version, name, down)) = file_name::parse_file_name(name) else { continue };
+        files.push((version, name.to_string(), down, file.to_string_lossy().into_owned()));
+    }
+    files.sort();
+    if let Some(version) = file_name::duplicate_version(files.iter().map(|f| (f.0, f.2))) {
+        return Err(syn::Error::new(dir.span(), format!("duplicate migration version {version} in {}", path.display())));
+    }
+
+    let mut migrations = Vec::new();
+    for (version, name, _, file) in files.iter().filter(|f| !f.2) {
+        let down = match files.iter().find(|f| f.0 == *version && f.2) {
+            Some((.., down)) => quote! { ::std::option::Option::Some(::std::borrow::Cow::Borrowed(include_str!(#down))) },
+            None => quote! { ::std::option::Option::None },
+        };
+        migrations.push(quote! {
+            ::pg_pool::migrate::Migration {
+                version: #version,
+                name: ::std::borrow::Cow::Borrowed(#name),
+                up: ::std::borrow::Cow::Borrowed(include_str!(#file)),
+                down: #down,
+            }
+        });
+    }
+    Ok(quote! { ::std::vec![#(#migrations),*] })
+}
@@ -0,0 +1,65 @@
+//! Applies migrations from a directory to the `db` configured in SV_CONF.
+//!
+//! Usage: pg-migrate [--dir <migrations>] [--table <history table>] <up|status|down|repair>
+use pg_pool::{PgPoolError, migrate::Migrator};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: pg-migrate [--dir <migrations>] [--table <history table>] <up|status|down|repair>";
+
+fn main() -> ExitCode {
+    let mut dir = "migrations".to_string();
+    let mut table = None;
+    let mut command = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dir" => dir = args.next().unwrap_or_default(),
+            "--table" => table = args.next(),
+            "up" | "status" | "down" | "repair" if command.is_none() => command = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let Some(command) = command else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    match runtime.block_on(run(&dir, table.as_deref(), &command)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(dir: &str, table: Option<&str>, command: &str) -> Result<(), PgPoolError> {
+    let mut migrator = Migrator::from_dir(dir)?;
+    if let Some(table) = table {
+        migrator = migrator.table(table);
+    }
+    match command {
+        "up" => {
+            let applied = migrator.up().await?;
+            println!("Applied {} migration(s) {applied:?}", applied.len());
+        }
+        "status" => {
+            for s in migrator.status().await? {
+                println!("{:>6}  {:<8}  {}", s.version, format!("{:?}", s.state), s.name);
+            }
+        }
+        "down" => match migrator.down().await? {
+            Some(version) => println!("Reverted migration {version}"),
+            None => println!("Nothing to revert"),
+        },
+        _ => {
+            let repaired = migrator.repair().await?;
+            println!("Repaired {} migration(s) {repaired:?}", repaired.len());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,234 @@
+//! Dynamic SQL with tracked bind parameters.
+//!
+//! Conditions are raw SQL fragments where each `?` becomes the next `$n`
+//! (write `??` for a literal `?`). Insert columns and tables are quoted.
+//! The same set of clauses always produces the same SQL, so statements stay cached.
+//!
+//! ```ignore
+//! let (sql, params) = builder::select("id, name", "users")
+//!     .where_opt("status = ?", status.as_ref())
+//!     .and("created_at >= ?", &[&since])
+//!     .order_by("name")
+//!     .limit(20)
+//!     .build();
+//! let rows = pgr::query(&sql, &params).await?;
+//! ```
+use deadpool_postgres::tokio_postgres::types::ToSql;
+use crate::quote::{quote_ident, quote_qualified};
+
+pub type Param<'a> = &'a (dyn ToSql + Sync);
+
+pub fn select<'a>(columns: &str, from: &str) -> Select<'a> {
+    Select {
+        columns: columns.to_string(),
+        from: from.to_string(),
+        conds: Vec::new(),
+        order: Vec::new(),
+        limit: None,
+        offset: None,
+        params: Vec::new(),
+    }
+}
+
+pub fn insert<'a>(table: &str) -> Insert<'a> {
+    Insert {
+        table: quote_qualified(table),
+        columns: Vec::new(),
+        conflict: None,
+        returning: None,
+        params: Vec::new(),
+    }
+}
+
+/// `insert` that updates the other inserted columns when `conflict` columns
+/// collide, or does nothing if there are none.
+pub fn upsert<'a>(table: &str, conflict: &[&str]) -> Insert<'a> {
+    Insert {
+        conflict: Some(conflict.iter().map(|c| quote_ident(c)).collect()),
+        ..insert(table)
+    }
+}
+
+pub struct Select<'a> {
+    columns: String,
+    from: String,
+    conds: Vec<String>,
+    order: Vec<String>,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    params: Vec<Param<'a>>,
+}
+
+impl<'a> Select<'a> {
+    /// Adds `cond` with one `?` bound to `value`, only when `value` is `Some`.
+    pub fn where_opt<T: ToSql + Sync>(self, cond: &str, value: Option<&'a T>) -> Self {
+        match value {
+            Some(v) => self.and(cond, &[v]),
+            None => self,
+        }
+    }
+
+    /// Adds `cond`, binding `params` to its `?` placeholders in order.
+    /// Panics if their counts differ.
+    pub fn and(mut self, cond: &str, params: &[Param<'a>]) -> Self {
+        let cond = number(cond, &mut self.params, params);
+        self.conds.push(cond);
+        self
+    }
+
+    pub fn order_by(mut self, expr: &str) -> Self {
+        self.order.push(expr.to_string());
+        self
+    }
+
+    pub fn limit(mut self, n: u64) -> Self {
+        self.limit = Some(n);
+        self
+    }
+
+    pub fn offset(mut self, n: u64) -> Self {
+        self.offset = Some(n);
+        self
+    }
+
+    pub fn build(self) -> (String, Vec<Param<'a>>) {
+        let mut sql = format!("SELECT {} FROM {}", self.columns, self.from);
+        match self.conds.len() {
+            0 => {}
+            1 => sql.push_str(&format!(" WHERE {}", self.conds[0])),
+            _ => {
+                let conds = self.conds.iter().map(|c| format!("({c})")).collect::<Vec<_>>();
+                sql.push_str(&format!(" WHERE {}", conds.join(" AND ")));
+            }
+        }
+        if !self.order.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", self.order.join(", ")));
+        }
+        if let Some(n) = self.limit {
+            sql.push_str(&format!(" LIMIT {n}"));
+        }
+        if let Some(n) = self.offset {
+            sql.push_str(&format!(" OFFSET {n}"));
+        }
+        (sql, self.params)
+    }
+}
+
+pub struct Insert<'a> {
+    table: String,
+    columns: Vec<String>,
+    conflict: Option<Vec<String>>,
+    returning: Option<String>,
+    params: Vec<Param<'a>>,
+}
+
+impl<'a> Insert<'a> {
+    pub fn value<T: ToSql + Sync>(mut self, column: &str, value: &'a T) -> Self {
+        self.columns.push(quote_ident(column));
+        self.params.push(value);
+        self
+    }
+
+    /// Sets `column` only when `value` is `Some`, leaving its default otherwise.
+    pub fn value_opt<T: ToSql + Sync>(self, column: &str, value: Option<&'a T>) -> Self {
+        match value {
+            Some(v) => self.value(column, v),
+            None => self,
+        }
+    }
+
+    /// Raw SQL list of columns or expressions to return.
+    pub fn returning(mut self, columns: &str) -> Self {
+        self.returning = Some(columns.to_string());
+        self
+    }
+
+    pub fn build(self) -> (String, Vec<Param<'a>>) {
+        let mut sql = format!("INSERT INTO {}", self.table);
+        if self.columns.is_empty() {
+            sql.push_str(" DEFAULT VALUES");
+        } else {
+            let values = (1..=self.params.len()).map(|n| format!("${n}")).collect::<Vec<_>>();
+            sql.push_str(&format!(" ({}) VALUES ({})", self.columns.join(", "), values.join(", ")));
+        }
+        if let Some(conflict) = &self.conflict {
+            let updates = self.columns.iter()
+                .filter(|c| !conflict.contains(c))
+                .map(|c| format!("{c} = EXCLUDED.{c}"))
+                .collect::<Vec<_>>();
+            sql.push_str(&format!(" ON CONFLICT ({})", conflict.join(", ")));
+            if updates.is_empty() {
+                sql.push_str(" DO NOTHING");
+            } else {
+                sql.push_str(&format!(" DO UPDATE SET {}", updates.join(", ")));
+            }
+        }
+        if let Some(returning) = &self.returning {
+            sql.push_str(&format!(" RETURNING {returning}"));
+        }
+        (sql, self.params)
+    }
+}
+
+/// Replaces each `?` in `fragment` with the next `$n` and appends `new` to `params`.
+fn number<'a>(fragment: &str, params: &mut Vec<Param<'a>>, new: &[Param<'a>]) -> String {
+    let mut out = String::with_capacity(fragment.len());
+    let mut chars = fragment.chars().peekable();
+    let mut bound = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '?' if chars.peek() == Some(&'?') => {
+                chars.next();
+                out.push('?');
+            }
+            '?' => {
+                bound += 1;
+                out.push_str(&format!("${}", params.len() + bound));
+            }
+            c => out.push(c),
+        }
+    }
+    assert_eq!(bound, new.len(), "`{fragment}` has {bound} placeholders but {} params", new.len());
+    params.extend_from_slice(new);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_numbers_optional_filters() {
+        let name: Option<String> = None;
+        let (sql, params) = select("id", "users")
+            .where_opt("name = ?", name.as_ref())
+            .where_opt("age >= ?", Some(&20i32))
+            .and("tags ?? 'a' OR id BETWEEN ? AND ?", &[&1i32, &9i32])
+            .order_by("id DESC")
+            .limit(10)
+            .offset(20)
+            .build();
+        assert_eq!(sql, "SELECT id FROM users WHERE (age >= $1) AND (tags ? 'a' OR id BETWEEN $2 AND $3) \
+            ORDER BY id DESC LIMIT 10 OFFSET 20");
+        assert_eq!(params.len(), 3);
+    }
+
+    #[test]
+    fn it_builds_upsert() {
+        let (sql, params) = upsert("app.users", &["id"])
+            .value("id", &1i32)
+            .value("name", &"a")
+            .value_opt::<i32>("age", None)
+            .returning("id")
+            .build();
+        assert_eq!(sql, "INSERT INTO \"app\".\"users\" (\"id\", \"name\") VALUES ($1, $2) \
+            ON CONFLICT (\"id\") DO UPDATE SET \"name\" = EXCLUDED.\"name\" RETURNING id");
+        assert_eq!(params.len(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_checks_placeholder_count() {
+        select("id", "users").and("a = ? AND b = ?", &[&1i32]);
+    }
+}
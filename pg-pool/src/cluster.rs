@@ -0,0 +1,453 @@
+//! Instance-based access to a writer and its read replicas.
+//!
+//! A `PgCluster` owns its pools and settings, so one process can talk to
+//! several databases. `pg::*`, `pgr::*` and `PgPool` executors run on
+//! `PG_CLUSTER`, which wraps `PG_POOL` and `PGR_POOL`. The counters behind
+//! `metrics()` are shared by every cluster.
+use deadpool_postgres::{
+    Client, Pool, PoolError,
+    tokio_postgres::{Statement, ToStatement, types::ToSql}
+};
+use crate::{
+    AdvisoryLock, BinaryCopyOutRow, CopyOptions, CopyOut, CopyRow, CredentialProvider, FromRow, InitError, Leader, Listener,
+    NamedParams, PgPoolError, QueryStream, QueryText, Row, Transaction, TxOptions, Type,
+    build_pool, copy, driver::{self, PgPool}, init, listen, lock, metrics::{ClusterStatus, PoolStatus},
+    metrics, retry::RetryPolicy, row, slow_query::SlowLog, stream, trace, transaction
+};
+use bytes::Bytes;
+use futures_util::Stream;
+use log::debug;
+use server_conf::DbConf;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[derive(Clone, Default)]
+pub struct ClusterOptions {
+    pub fallback: bool,   // Serve reads from the writer when no reader hands out a connection
+    pub credentials: Option<Arc<dyn CredentialProvider>>,   // Passwords for every pool; see `set_credentials`
+}
+
+impl fmt::Debug for ClusterOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClusterOptions")
+            .field("fallback", &self.fallback)
+            .field("credentials", &self.credentials.is_some())
+            .finish()
+    }
+}
+
+/// A writer and any number of readers. Cloning shares the pools.
+#[derive(Clone)]
+pub struct PgCluster {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    writer: Node,
+    readers: Vec<Node>,
+    fallback: bool,
+    credentials: Option<Arc<dyn CredentialProvider>>,
+    next: AtomicUsize,   // Round-robin position over `readers`
+    roles: [Role; 2],    // Indexed by `PgPool as usize`
+}
+
+struct Node {
+    pool: Pool,
+    db: DbConf,
+}
+
+/// Settings applied per statement. Readers share the first reader's config.
+pub(crate) struct Role {
+    pub(crate) retry: RetryPolicy,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) slow_log: Option<SlowLog>,
+}
+
+impl Role {
+    fn new(pool: PgPool, db: &DbConf) -> Self {
+        Self {
+            retry: match pool {
+                PgPool::Reader => RetryPolicy::from_conf(db),
+                PgPool::Writer => RetryPolicy::none(),
+            },
+            timeout: db.query_timeout.map(Duration::from_millis),
+            slow_log: SlowLog::from_conf(db),
+        }
+    }
+}
+
+impl PgCluster {
+    /// Builds the pools without connecting. See `init` to fail fast.
+    pub fn new(writer: DbConf, readers: Vec<DbConf>, options: ClusterOptions) -> Result<Self, InitError> {
+        let pool = |role, db: &DbConf| build_pool(db, options.fallback, options.credentials.clone())
+            .map_err(|message| InitError::Config { pool: role, message });
+        let writer = (pool(PgPool::Writer, &writer)?, writer);
+        let readers = readers.into_iter()
+            .map(|db| Ok((pool(PgPool::Reader, &db)?, db)))
+            .collect::<Result<Vec<_>, InitError>>()?;
+        Ok(Self::from_pools(writer, readers, options))
+    }
+
+    pub(crate) fn from_pools(writer: (Pool, DbConf), readers: Vec<(Pool, DbConf)>, options: ClusterOptions) -> Self {
+        let writer = Node { pool: writer.0, db: writer.1 };
+        let readers = readers.into_iter()
+            .map(|(pool, db)| Node { pool, db })
+            .collect::<Vec<_>>();
+        let roles = [
+            Role::new(PgPool::Writer, &writer.db),
+            Role::new(PgPool::Reader, readers.first().map_or(&writer.db, |r| &r.db)),
+        ];
+        Self {
+            inner: Arc::new(Inner {
+                writer,
+                readers,
+                fallback: options.fallback,
+                credentials: options.credentials,
+                next: AtomicUsize::new(0),
+                roles,
+            })
+        }
+    }
+
+    pub fn writer(&self) -> PgHandle<'_> {
+        self.handle(PgPool::Writer)
+    }
+
+    /// Reads spread round-robin over the readers, or go to the writer without any.
+    pub fn reader(&self) -> PgHandle<'_> {
+        self.handle(PgPool::Reader)
+    }
+
+    pub fn handle(&self, pool: PgPool) -> PgHandle<'_> {
+        PgHandle { cluster: self, pool }
+    }
+
+    /// Opens `min_idle` connections in every pool and runs a probe query on each.
+    /// Readers are checked on their own, without falling back to the writer.
+    pub async fn init(&self) -> Result<(), InitError> {
+        init::warm_up(PgPool::Writer, &self.inner.writer.db, &self.inner.writer.pool).await?;
+        for reader in &self.inner.readers {
+            init::warm_up(PgPool::Reader, &reader.db, &reader.pool).await?;
+        }
+        Ok(())
+    }
+
+    pub fn status(&self) -> ClusterStatus {
+        ClusterStatus {
+            writer: PoolStatus::from(self.inner.writer.pool.status()),
+            readers: self.inner.readers.iter().map(|r| PoolStatus::from(r.pool.status())).collect(),
+        }
+    }
+
+    /// Starts competing for leadership on `key` over a dedicated connection to the writer.
+    pub fn leader(&self, key: i64, interval: Duration) -> Result<Leader, PgPoolError> {
+        lock::elect(&self.inner.writer.db, self.inner.credentials.clone(), key, interval)
+    }
+
+    /// Subscribes to `channels` on a dedicated connection to the writer.
+    pub async fn listen(&self, channels: &[&str]) -> Result<Listener, PgPoolError> {
+        listen::subscribe(&self.inner.writer.db, self.inner.credentials.clone(), channels).await
+    }
+
+    pub fn close(&self) {
+        self.writer().close();
+        self.reader().close();
+    }
+
+    pub(crate) fn role(&self, pool: PgPool) -> &Role {
+        &self.inner.roles[pool as usize]
+    }
+
+    /// Checks out a client, returning the pool that served it. A failing
+    /// reader is skipped for the next one before falling back to the writer.
+    pub(crate) async fn checkout(&self, pool: PgPool) -> (PgPool, Result<Client, PoolError>) {
+        let inner = &*self.inner;
+        let n = inner.readers.len();
+        if pool == PgPool::Writer || n == 0 {
+            trace::served_by(PgPool::Writer);
+            return (PgPool::Writer, inner.writer.pool.get().await);
+        }
+
+        let start = inner.next.fetch_add(1, Ordering::Relaxed);
+        let mut result = inner.readers[start % n].pool.get().await;
+        for i in 1..n {
+            let Err(e) = &result else { break };
+            debug!("Try next reader DB: {e}");
+            result = inner.readers[(start + i) % n].pool.get().await;
+        }
+        match result {
+            Err(e) if inner.fallback => {
+                debug!("Fallback to writer DB: {}", e);
+                metrics::fallback();
+                trace::served_by(PgPool::Writer);
+                (PgPool::Writer, inner.writer.pool.get().await)
+            }
+            result => {
+                trace::served_by(PgPool::Reader);
+                (PgPool::Reader, result)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for PgCluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PgCluster")
+            .field("writer", &self.inner.writer.db.name)
+            .field("readers", &self.inner.readers.len())
+            .field("fallback", &self.inner.fallback)
+            .finish()
+    }
+}
+
+/// The writer or the readers of a cluster, with the `pg` / `pgr` operations.
+#[derive(Clone, Copy, Debug)]
+pub struct PgHandle<'a> {
+    pub(crate) cluster: &'a PgCluster,
+    pub(crate) pool: PgPool,
+}
+
+impl<'a> PgHandle<'a> {
+    pub fn cluster(&self) -> &'a PgCluster {
+        self.cluster
+    }
+
+    pub fn pool(&self) -> PgPool {
+        self.pool
+    }
+
+    pub(crate) fn role(&self) -> &'a Role {
+        self.cluster.role(self.pool)
+    }
+
+    pub async fn prepare(&self, query: &str) -> Result<Statement, PgPoolError> {
+        driver::prepare(*self, query).await
+    }
+
+    pub async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::query(*self, statement, params).await
+    }
+
+    pub async fn query_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError> {
+        driver::query_pp(*self, query, types, params).await
+    }
+
+    pub async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::query_one(*self, statement, params).await
+    }
+
+    pub async fn query_one_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError> {
+        driver::query_one_pp(*self, query, types, params).await
+    }
+
+    pub async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::query_opt(*self, statement, params).await
+    }
+
+    pub async fn query_opt_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError> {
+        driver::query_opt_pp(*self, query, types, params).await
+    }
+
+    /// `query` cancelled on the server when it runs longer than `timeout`,
+    /// failing with `PgPoolError::QueryTimeout`. Overrides `query_timeout` from the config.
+    pub async fn query_timeout<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)], timeout: Duration) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::query_timeout(*self, statement, params, Some(timeout)).await
+    }
+
+    pub async fn query_one_timeout<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)], timeout: Duration) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::query_one_timeout(*self, statement, params, Some(timeout)).await
+    }
+
+    pub async fn query_opt_timeout<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)], timeout: Duration) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::query_opt_timeout(*self, statement, params, Some(timeout)).await
+    }
+
+    pub async fn execute_timeout<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)], timeout: Duration) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::execute_timeout(*self, statement, params, Some(timeout)).await
+    }
+
+    /// Query with `:name` placeholders, e.g. `WHERE id = :id`, bound from `params`.
+    pub async fn query_named<P>(&self, query: &str, params: &P) -> Result<Vec<Row>, PgPoolError>
+    where
+        P: ?Sized + NamedParams,
+    {
+        driver::query_named(*self, query, params).await
+    }
+
+    pub async fn query_one_named<P>(&self, query: &str, params: &P) -> Result<Row, PgPoolError>
+    where
+        P: ?Sized + NamedParams,
+    {
+        driver::query_one_named(*self, query, params).await
+    }
+
+    pub async fn query_opt_named<P>(&self, query: &str, params: &P) -> Result<Option<Row>, PgPoolError>
+    where
+        P: ?Sized + NamedParams,
+    {
+        driver::query_opt_named(*self, query, params).await
+    }
+
+    /// `query` mapping each row with `FromRow`.
+    pub async fn query_as<R: FromRow>(
+        &self,
+        statement: &(impl ?Sized + ToStatement + QueryText),
+        params: &[&(dyn ToSql + Sync)]
+    ) -> Result<Vec<R>, PgPoolError>
+    {
+        row::from_rows(driver::query(*self, statement, params).await?)
+    }
+
+    pub async fn query_one_as<R: FromRow>(
+        &self,
+        statement: &(impl ?Sized + ToStatement + QueryText),
+        params: &[&(dyn ToSql + Sync)]
+    ) -> Result<R, PgPoolError>
+    {
+        R::from_row(&driver::query_one(*self, statement, params).await?)
+    }
+
+    pub async fn query_opt_as<R: FromRow>(
+        &self,
+        statement: &(impl ?Sized + ToStatement + QueryText),
+        params: &[&(dyn ToSql + Sync)]
+    ) -> Result<Option<R>, PgPoolError>
+    {
+        driver::query_opt(*self, statement, params).await?
+            .map(|row| R::from_row(&row))
+            .transpose()
+    }
+
+    /// Streams rows through a server side cursor, fetching `fetch_size` rows per round trip.
+    pub async fn query_stream(
+        &self,
+        query: &str,
+        types: &[Type],
+        params: &[&(dyn ToSql + Sync)],
+        fetch_size: u32
+    ) -> Result<QueryStream, PgPoolError>
+    {
+        stream::query_stream(*self, query, types, params, fetch_size).await
+    }
+
+    pub async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText,
+    {
+        driver::execute(*self, statement, params).await
+    }
+
+    pub async fn execute_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError> {
+        driver::execute_pp(*self, query, types, params).await
+    }
+
+    pub async fn execute_named<P>(&self, query: &str, params: &P) -> Result<u64, PgPoolError>
+    where
+        P: ?Sized + NamedParams,
+    {
+        driver::execute_named(*self, query, params).await
+    }
+
+    /// Reader transactions are always read-only.
+    pub async fn transaction<T, F>(&self, opts: TxOptions, f: F) -> Result<T, PgPoolError>
+    where
+        F: AsyncFnMut(&mut Transaction<'_>) -> Result<T, PgPoolError>,
+    {
+        let opts = match self.pool {
+            PgPool::Reader => TxOptions { read_only: true, ..opts },
+            PgPool::Writer => opts,
+        };
+        transaction::transaction(*self, opts, f).await
+    }
+
+    /// Bulk loads `rows` with binary `COPY ... FROM STDIN`. Returns the number of rows copied.
+    pub async fn copy_in<S, R>(&self, table: &str, columns: &[&str], rows: S) -> Result<u64, PgPoolError>
+    where
+        S: Stream<Item = R>,
+        R: CopyRow,
+    {
+        copy::copy_in(*self, table, columns, rows, CopyOptions::default()).await
+    }
+
+    pub async fn copy_in_with<S, R>(
+        &self,
+        table: &str,
+        columns: &[&str],
+        rows: S,
+        opts: CopyOptions
+    ) -> Result<u64, PgPoolError>
+    where
+        S: Stream<Item = R>,
+        R: CopyRow,
+    {
+        copy::copy_in(*self, table, columns, rows, opts).await
+    }
+
+    /// Exports `query` with binary `COPY ... TO STDOUT`, decoding rows by the query's column types.
+    pub async fn copy_out(&self, query: &str) -> Result<CopyOut<BinaryCopyOutRow>, PgPoolError> {
+        copy::copy_out(*self, query, CopyOptions::default()).await
+    }
+
+    pub async fn copy_out_with(&self, query: &str, opts: CopyOptions) -> Result<CopyOut<BinaryCopyOutRow>, PgPoolError> {
+        copy::copy_out(*self, query, opts).await
+    }
+
+    /// Exports `query` as raw CSV bytes with a header line.
+    pub async fn copy_out_csv(&self, query: &str) -> Result<CopyOut<Bytes>, PgPoolError> {
+        copy::copy_out_csv(*self, query, CopyOptions::default()).await
+    }
+
+    pub async fn copy_out_csv_with(&self, query: &str, opts: CopyOptions) -> Result<CopyOut<Bytes>, PgPoolError> {
+        copy::copy_out_csv(*self, query, opts).await
+    }
+
+    /// Waits for the session advisory lock `key`. See `lock_key` for named locks.
+    pub async fn advisory_lock(&self, key: i64) -> Result<AdvisoryLock, PgPoolError> {
+        lock::advisory_lock(*self, key).await
+    }
+
+    /// `None` when another session holds `key`.
+    pub async fn try_advisory_lock(&self, key: i64) -> Result<Option<AdvisoryLock>, PgPoolError> {
+        lock::try_advisory_lock(*self, key).await
+    }
+
+    pub async fn prepare_typed_cached(&self, query: &str, types: &[Type]) -> Result<Statement, PgPoolError> {
+        Ok(self.get().await?
+            .prepare_typed_cached(query, types).await?)
+    }
+
+    pub async fn get(&self) -> Result<Client, PgPoolError> {
+        driver::get(*self).await
+    }
+
+    pub fn close(&self) {
+        let inner = &self.cluster.inner;
+        match self.pool {
+            PgPool::Writer => driver::close(&inner.writer.pool),
+            PgPool::Reader => inner.readers.iter().for_each(|r| driver::close(&r.pool)),
+        }
+    }
+}
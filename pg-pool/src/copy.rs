@@ -0,0 +1,179 @@
+use bytes::Bytes;
+use deadpool_postgres::{
+    Client, Object,
+    tokio_postgres::{
+        Error,
+        binary_copy::{BinaryCopyInWriter, BinaryCopyOutRow, BinaryCopyOutStream},
+        types::ToSql,
+    },
+};
+use futures_util::{Stream, StreamExt, pin_mut};
+use crate::{PgHandle, PgPoolError, driver, quote::{quote_ident, quote_qualified}};
+use std::pin::Pin;
+use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::task::{Context, Poll};
+
+/// Progress reporting and cancellation for COPY.
+#[derive(Default)]
+pub struct CopyOptions {
+    /// Called with the running total of rows (binary) or bytes (CSV).
+    pub on_progress: Option<Box<dyn FnMut(u64) + Send>>,
+    /// Rows or bytes between `on_progress` calls. 0 reports every item.
+    pub progress_interval: u64,
+    /// Aborts the COPY once set. COPY IN rolls back all rows.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+impl CopyOptions {
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+
+    fn report(&mut self, last: u64, total: u64) {
+        if let Some(f) = self.on_progress.as_mut() {
+            if self.progress_interval == 0 || total / self.progress_interval > last / self.progress_interval {
+                f(total);
+            }
+        }
+    }
+}
+
+/// Row written by `pg::copy_in`.
+pub trait CopyRow {
+    fn values(&self) -> Vec<&(dyn ToSql + Sync)>;
+}
+
+impl CopyRow for Vec<Box<dyn ToSql + Send + Sync>> {
+    fn values(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.iter().map(|v| v.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+pub(crate) async fn copy_in<S, R>(
+    db: PgHandle<'_>,
+    table: &str,
+    columns: &[&str],
+    rows: S,
+    mut opts: CopyOptions
+) -> Result<u64, PgPoolError>
+where
+    S: Stream<Item = R>,
+    R: CopyRow,
+{
+    let client = driver::get(db).await?;
+    let table = quote_qualified(table);
+    let columns = columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ");
+    let types = client.prepare(&format!("SELECT {columns} FROM {table} LIMIT 0")).await?
+        .columns().iter()
+        .map(|c| c.type_().clone())
+        .collect::<Vec<_>>();
+
+    let sink = client.copy_in(&format!("COPY {table} ({columns}) FROM STDIN (FORMAT binary)")).await?;
+    let writer = BinaryCopyInWriter::new(sink, &types);
+    pin_mut!(writer);
+    pin_mut!(rows);
+    let mut count = 0;
+    while let Some(row) = rows.next().await {
+        // Dropping the writer sends CopyFail
+        if opts.cancelled() {
+            return Err(PgPoolError::Cancelled);
+        }
+        writer.as_mut().write(&row.values()).await?;
+        opts.report(count, count + 1);
+        count += 1;
+    }
+    Ok(writer.finish().await?)
+}
+
+pub(crate) async fn copy_out(
+    db: PgHandle<'_>,
+    query: &str,
+    opts: CopyOptions
+) -> Result<CopyOut<BinaryCopyOutRow>, PgPoolError> {
+    let client = driver::get(db).await?;
+    let types = client.prepare(query).await?
+        .columns().iter()
+        .map(|c| c.type_().clone())
+        .collect::<Vec<_>>();
+    let stream = client.copy_out(&format!("COPY ({query}) TO STDOUT (FORMAT binary)")).await?;
+    let stream = BinaryCopyOutStream::new(stream, &types);
+    Ok(CopyOut::new(client, stream, opts, |_: &BinaryCopyOutRow| 1))
+}
+
+pub(crate) async fn copy_out_csv(
+    db: PgHandle<'_>,
+    query: &str,
+    opts: CopyOptions
+) -> Result<CopyOut<Bytes>, PgPoolError> {
+    let client = driver::get(db).await?;
+    let stream = client.copy_out(&format!("COPY ({query}) TO STDOUT (FORMAT csv, HEADER)")).await?;
+    Ok(CopyOut::new(client, stream, opts, |b: &Bytes| b.len() as u64))
+}
+
+/// COPY OUT stream holding its pooled client. Dropping it before the end, or
+/// cancelling, discards the connection instead of returning it to the pool.
+pub struct CopyOut<T> {
+    client: Option<Client>,
+    inner: Pin<Box<dyn Stream<Item = Result<T, Error>> + Send>>,
+    opts: CopyOptions,
+    total: u64,
+    weight: fn(&T) -> u64,
+    done: bool,
+}
+
+impl<T> CopyOut<T> {
+    fn new<S>(client: Client, stream: S, opts: CopyOptions, weight: fn(&T) -> u64) -> Self
+    where
+        S: Stream<Item = Result<T, Error>> + Send + 'static,
+    {
+        Self { client: Some(client), inner: Box::pin(stream), opts, total: 0, weight, done: false }
+    }
+
+    fn discard(&mut self) {
+        self.done = true;
+        if let Some(client) = self.client.take() {
+            drop(Object::take(client));
+        }
+    }
+}
+
+impl<T> Stream for CopyOut<T> {
+    type Item = Result<T, PgPoolError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if this.opts.cancelled() {
+            this.discard();
+            return Poll::Ready(Some(Err(PgPoolError::Cancelled)));
+        }
+        match this.inner.as_mut().poll_next(cx) {
+            Poll::Ready(Some(Ok(item))) => {
+                let last = this.total;
+                this.total += (this.weight)(&item);
+                this.opts.report(last, this.total);
+                Poll::Ready(Some(Ok(item)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.discard();
+                Poll::Ready(Some(Err(e.into())))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                this.client.take();
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for CopyOut<T> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.discard();
+        }
+    }
+}
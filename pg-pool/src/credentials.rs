@@ -0,0 +1,167 @@
+//! Passwords looked up for every new connection, so rotated passwords and
+//! short-lived tokens are picked up without rebuilding the pools.
+//!
+//! The provider is, in order: the one in `ClusterOptions::credentials`, the
+//! one registered with `set_credentials`, or one from the config, reading
+//! `password_file` when set and `password` otherwise. When a provider fails
+//! the last password it returned is used.
+use deadpool_postgres::{Connect, tokio_postgres::{Client, Config, Error, NoTls}};
+use futures_util::future::BoxFuture;
+use log::warn;
+use server_conf::DbConf;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+
+pub type CredentialError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Supplies the password for a new connection to `db`.
+pub trait CredentialProvider: Send + Sync {
+    fn password<'a>(&'a self, db: &'a DbConf) -> BoxFuture<'a, Result<String, CredentialError>>;
+}
+
+#[derive(Debug, Clone)]
+pub struct StaticPassword(pub String);
+
+impl CredentialProvider for StaticPassword {
+    fn password<'a>(&'a self, _: &'a DbConf) -> BoxFuture<'a, Result<String, CredentialError>> {
+        Box::pin(async { Ok(self.0.clone()) })
+    }
+}
+
+/// Password in a file, re-read when its modification time changes.
+/// A trailing newline is ignored.
+#[derive(Debug)]
+pub struct PasswordFile {
+    path: PathBuf,
+    cached: Mutex<Option<(SystemTime, String)>>,
+}
+
+impl PasswordFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into(), cached: Mutex::new(None) }
+    }
+
+    fn read(&self) -> Result<String, CredentialError> {
+        let modified = std::fs::metadata(&self.path)?.modified()?;
+        let mut cached = self.cached.lock().unwrap();
+        match cached.as_ref() {
+            Some((at, password)) if *at == modified => Ok(password.clone()),
+            _ => {
+                let password = std::fs::read_to_string(&self.path)?
+                    .trim_end_matches(['\r', '\n'])
+                    .to_string();
+                *cached = Some((modified, password.clone()));
+                Ok(password)
+            }
+        }
+    }
+}
+
+impl CredentialProvider for PasswordFile {
+    fn password<'a>(&'a self, _: &'a DbConf) -> BoxFuture<'a, Result<String, CredentialError>> {
+        Box::pin(async { self.read() })
+    }
+}
+
+/// Password from an async callback, e.g. one that fetches a short-lived token.
+pub struct PasswordFn<F>(pub F);
+
+impl<F, Fut> CredentialProvider for PasswordFn<F>
+where
+    F: Fn(&DbConf) -> Fut + Send + Sync,
+    Fut: Future<Output = Result<String, CredentialError>> + Send + 'static,
+{
+    fn password<'a>(&'a self, db: &'a DbConf) -> BoxFuture<'a, Result<String, CredentialError>> {
+        Box::pin((self.0)(db))
+    }
+}
+
+static CREDENTIALS: RwLock<Option<Arc<dyn CredentialProvider>>> = RwLock::new(None);
+
+/// Sets the provider for `PG_POOL`, `PGR_POOL` and pools from `create_pool`.
+/// Takes effect for the next connection, also in pools that already exist.
+pub fn set_credentials(provider: impl CredentialProvider + 'static) {
+    *CREDENTIALS.write().unwrap() = Some(Arc::new(provider));
+}
+
+/// Opens connections to `db` with the current password.
+pub(crate) struct Connector {
+    db: DbConf,
+    config: Config,
+    provider: Option<Arc<dyn CredentialProvider>>,
+    from_conf: Arc<dyn CredentialProvider>,
+    last: Mutex<String>,
+}
+
+impl Connector {
+    pub(crate) fn new(db: &DbConf, config: Config, provider: Option<Arc<dyn CredentialProvider>>) -> Self {
+        let from_conf: Arc<dyn CredentialProvider> = match &db.password_file {
+            Some(path) => Arc::new(PasswordFile::new(path)),
+            None => Arc::new(StaticPassword(db.password.clone())),
+        };
+        Self { db: db.clone(), config, provider, from_conf, last: Mutex::new(db.password.clone()) }
+    }
+
+    async fn password(&self) -> String {
+        let provider = self.provider.clone()
+            .or_else(|| CREDENTIALS.read().unwrap().clone())
+            .unwrap_or_else(|| self.from_conf.clone());
+        match provider.password(&self.db).await {
+            Ok(password) => {
+                password.clone_into(&mut self.last.lock().unwrap());
+                password
+            }
+            Err(e) => {
+                warn!("Cannot get pg password for {}, using the last one: {e}", self.db.user);
+                self.last.lock().unwrap().clone()
+            }
+        }
+    }
+
+    /// Settings for a connection outside the pool.
+    pub(crate) async fn config(&self) -> Config {
+        let mut config = self.config.clone();
+        config.password(self.password().await);
+        config
+    }
+}
+
+impl Connect for Connector {
+    fn connect(&self, config: &Config) -> BoxFuture<'_, Result<(Client, JoinHandle<()>), Error>> {
+        let mut config = config.clone();
+        Box::pin(async move {
+            config.password(self.password().await);
+            let (client, conn) = config.connect(NoTls).await?;
+            let task = tokio::spawn(async move {
+                if let Err(e) = conn.await {
+                    warn!("Connection error: {e}");
+                }
+            });
+            Ok((client, task))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_rereads_changed_password_file() {
+        let path = std::env::temp_dir().join(format!("pg_pool_password_{}", std::process::id()));
+        std::fs::write(&path, "first\n").unwrap();
+        let file = PasswordFile::new(&path);
+        assert_eq!(file.read().unwrap(), "first");
+
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, "second").unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1)).unwrap();
+        assert_eq!(file.read().unwrap(), "second");
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.read().is_err());
+    }
+}
@@ -1,139 +1,339 @@
 use deadpool_postgres::{
-    Client,
-    Pool, PoolError,
+    Client, Object,
+    Pool,
     tokio_postgres::{
-        Error, Statement, ToStatement,
+        Error, NoTls, Statement, ToStatement,
         error::SqlState, types::ToSql
     }
 };
-use crate::{PG_POOL, PGR_POOL, Row, Type};
-use log::debug;
-use server_conf::SV_CONF;
-use std::sync::LazyLock;
+use crate::{PgHandle, PgPoolError, Row, Type, metrics, named::{self, NamedParams}, retry::retry, slow_query::{QueryText, RowCount, timed}, trace::in_span};
+use log::{debug, warn};
+use std::time::{Duration, Instant};
 
-#[derive(Copy, Clone, PartialEq)]
+/// Pool handle on `PG_CLUSTER`. `Reader` routes to the read replica when `dbr` is configured.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PgPool {
     Writer = 0,
     Reader = 1,
 }
 
-pub async fn prepare(pool: PgPool, query: &str) -> Result<Statement, Error> {
-    let client = get(pool).await.unwrap();
-    client.prepare(query).await
+impl PgPool {
+    pub fn name(self) -> &'static str {
+        match self {
+            PgPool::Writer => "writer",
+            PgPool::Reader => "reader",
+        }
+    }
+}
+
+/// Evaluates `$body` with a pooled client. A connection that fails or times
+/// out mid-request is detached from the pool and the error returned, since
+/// `$body` may already have run. Retrying is left to the role's policy, so
+/// only readers and callers that opted in send it again.
+/// When `$timeout` expires the query is cancelled on the server, so a late
+/// cancel cannot hit the next query.
+macro_rules! with_client {
+    ($pool:expr, $timeout:expr, |$client:ident| $body:expr) => {{
+        let timeout: Option<Duration> = $timeout;
+        let $client = get($pool).await?;
+        let result = deadline!(timeout, $client, $body);
+        if matches!(&result, Err(err) if err.is_closed() || err.is_query_timeout()) {
+            drop(Object::take($client));
+        }
+        result
+    }};
+}
+
+/// Evaluates `$body`, cancelling it on the server after `$timeout`.
+macro_rules! deadline {
+    ($timeout:expr, $client:ident, $body:expr) => {{
+        let query = async { $body };
+        match $timeout {
+            None => query.await.map_err(PgPoolError::from),
+            Some(timeout) => match tokio::time::timeout(timeout, query).await {
+                Ok(result) => result.map_err(PgPoolError::from),
+                Err(_) => {
+                    cancel(&$client, timeout).await;
+                    Err(PgPoolError::QueryTimeout(timeout))
+                }
+            }
+        }
+    }};
+}
+
+/// Evaluates `$body` with the cached statement for `$query`. When the server
+/// no longer knows the statement or its plan is stale, the cache is cleared
+/// and `$body` is retried once with a freshly prepared statement.
+macro_rules! with_cached {
+    ($client:expr, $query:expr, $types:expr, |$stmt:ident| $body:expr) => {
+        async {
+            let cached = $client.statement_cache.size();
+            let $stmt = $crate::trace::in_span!(
+                $crate::trace::prepare($query), $client.prepare_typed_cached($query, $types)
+            )?;
+            $crate::metrics::cache($client.statement_cache.size() == cached);
+            match $body {
+                Err(err) if $crate::driver::invalid_statement(&err) => {
+                    log::debug!("Re-prepare invalidated statement: {err}");
+                    $client.statement_cache.clear();
+                    let $stmt = $crate::trace::in_span!(
+                        $crate::trace::prepare($query), $client.prepare_typed_cached($query, $types)
+                    )?;
+                    $crate::metrics::cache(false);
+                    $body
+                }
+                result => result
+            }
+        }.await
+    };
+}
+pub(crate) use with_cached;
+
+pub async fn prepare(db: PgHandle<'_>, query: &str) -> Result<Statement, PgPoolError> {
+    in_span!(crate::trace::prepare(query), retry(&db.role().retry, || async move {
+        with_client!(db, db.role().timeout, |client| client.prepare(query).await)
+    }))
 }
 
 pub async fn query<T>(
-    pool: PgPool,
+    db: PgHandle<'_>,
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Vec<Row>, Error>
+) -> Result<Vec<Row>, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    let client = get(pool).await.unwrap();
-    client.query(statement, params).await
+    query_timeout(db, statement, params, db.role().timeout).await
+}
+
+pub async fn query_timeout<T>(
+    db: PgHandle<'_>,
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Option<Duration>
+) -> Result<Vec<Row>, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    observe(db, statement.query_text(), params, retry(&db.role().retry, || async move {
+        with_client!(db, timeout, |client| client.query(statement, params).await)
+    })).await
 }
 
 pub async fn query_pp(
-    pool: PgPool,
+    db: PgHandle<'_>,
     query: &str,
     types: &[Type],
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Vec<Row>, Box<dyn std::error::Error + Send + Sync + 'static>>
-{
-    let client = get(pool).await.unwrap();
-    let stmt = client.prepare_typed_cached(query, types).await?;
-    let result = client.query(&stmt, params).await;
-    if result.is_ok() { return Ok(result?); }
-
-    let err = result.unwrap_err();
-    if err.is_closed() ||
-        err.code() == Some(&SqlState::UNDEFINED_PSTATEMENT) {
-            client.statement_cache.clear();
-            let stmt2 = client.prepare_typed_cached(query, types).await?;
-            return Ok(client.query(&stmt2, params).await
-                      .map_err(|e| Box::new(e))?);
-        }
-    Err(Box::new(err))
+) -> Result<Vec<Row>, PgPoolError>
+{
+    observe(db, Some(query), params, retry(&db.role().retry, || async move {
+        with_client!(db, db.role().timeout, |client| {
+            with_cached!(client, query, types, |stmt| client.query(&stmt, params).await)
+        })
+    })).await
 }
 
 pub async fn query_one<T>(
-    pool: PgPool,
+    db: PgHandle<'_>,
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Row, Error>
+) -> Result<Row, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    let client = get(pool).await.unwrap();
-    client.query_one(statement, params).await
+    query_one_timeout(db, statement, params, db.role().timeout).await
+}
+
+pub async fn query_one_timeout<T>(
+    db: PgHandle<'_>,
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Option<Duration>
+) -> Result<Row, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    observe(db, statement.query_text(), params, retry(&db.role().retry, || async move {
+        with_client!(db, timeout, |client| client.query_one(statement, params).await)
+    })).await
 }
 
 pub async fn query_one_pp(
-    pool: PgPool,
+    db: PgHandle<'_>,
     query: &str,
     types: &[Type],
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Row, Box<dyn std::error::Error + Send + Sync + 'static>>
-{
-    let client = get(pool).await.unwrap();
-    let stmt = client.prepare_typed_cached(query, types).await?;
-    let result = client.query_one(&stmt, params).await;
-    if result.is_ok() { return Ok(result?); }
-
-    let err = result.unwrap_err();
-    if err.is_closed() ||
-        err.code() == Some(&SqlState::UNDEFINED_PSTATEMENT) {
-            client.statement_cache.clear();
-            let stmt2 = client.prepare_typed_cached(query, types).await?;
-            return Ok(client.query_one(&stmt2, params).await
-                      .map_err(|e| Box::new(e))?);
-        }
-    Err(Box::new(err))
+) -> Result<Row, PgPoolError>
+{
+    observe(db, Some(query), params, retry(&db.role().retry, || async move {
+        with_client!(db, db.role().timeout, |client| {
+            with_cached!(client, query, types, |stmt| client.query_one(&stmt, params).await)
+        })
+    })).await
 }
 
 pub async fn query_opt<T>(
-    pool: PgPool,
+    db: PgHandle<'_>,
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Option<Row>, Error>
+) -> Result<Option<Row>, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    query_opt_timeout(db, statement, params, db.role().timeout).await
+}
+
+pub async fn query_opt_timeout<T>(
+    db: PgHandle<'_>,
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Option<Duration>
+) -> Result<Option<Row>, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
+{
+    observe(db, statement.query_text(), params, retry(&db.role().retry, || async move {
+        with_client!(db, timeout, |client| client.query_opt(statement, params).await)
+    })).await
+}
+
+pub async fn query_opt_pp(
+    db: PgHandle<'_>,
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Option<Row>, PgPoolError>
 {
-    let client = get(pool).await.unwrap();
-    client.query_opt(statement, params).await
+    observe(db, Some(query), params, retry(&db.role().retry, || async move {
+        with_client!(db, db.role().timeout, |client| {
+            with_cached!(client, query, types, |stmt| client.query_opt(&stmt, params).await)
+        })
+    })).await
 }
 
 pub async fn execute<T>(
-    pool: PgPool,
+    db: PgHandle<'_>,
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<u64, Error>
+) -> Result<u64, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    let client = get(pool).await.unwrap();
-    client.execute(statement, params).await
+    execute_timeout(db, statement, params, db.role().timeout).await
 }
 
-pub async fn get(pool: PgPool) -> Result<Client, PoolError> {
-    if pool == PgPool::Writer || LazyLock::force(&PGR_POOL).is_none() {
-        return PG_POOL.get().await;
-    }
+pub async fn execute_timeout<T>(
+    db: PgHandle<'_>,
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Option<Duration>
+) -> Result<u64, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    observe(db, statement.query_text(), params, async {
+        with_client!(db, timeout, |client| client.execute(statement, params).await)
+    }).await
+}
+
+pub async fn execute_pp(
+    db: PgHandle<'_>,
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<u64, PgPoolError>
+{
+    observe(db, Some(query), params, async {
+        with_client!(db, db.role().timeout, |client| {
+            with_cached!(client, query, types, |stmt| client.execute(&stmt, params).await)
+        })
+    }).await
+}
+
+pub async fn query_named<P>(db: PgHandle<'_>, query: &str, params: &P) -> Result<Vec<Row>, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    let named = named::rewrite_cached(query)?;
+    query_pp(db, &named.sql, &[], &named.bind(params)?).await
+}
+
+pub async fn query_one_named<P>(db: PgHandle<'_>, query: &str, params: &P) -> Result<Row, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    let named = named::rewrite_cached(query)?;
+    query_one_pp(db, &named.sql, &[], &named.bind(params)?).await
+}
+
+pub async fn query_opt_named<P>(db: PgHandle<'_>, query: &str, params: &P) -> Result<Option<Row>, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    let named = named::rewrite_cached(query)?;
+    query_opt_pp(db, &named.sql, &[], &named.bind(params)?).await
+}
+
+pub async fn execute_named<P>(db: PgHandle<'_>, query: &str, params: &P) -> Result<u64, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    let named = named::rewrite_cached(query)?;
+    execute_pp(db, &named.sql, &[], &named.bind(params)?).await
+}
 
-    let result = PGR_POOL.as_ref().unwrap().get().await;
-    if SV_CONF.dbr.as_ref().unwrap().fallback && result.is_err() {
-        debug!("Fallback to writer DB: {}", result.unwrap_err());
-        return PG_POOL.get().await;
+/// Checks out a client, replacing connections that closed while idle.
+pub async fn get(db: PgHandle<'_>) -> Result<Client, PgPoolError> {
+    loop {
+        let start = Instant::now();
+        let (served, client) = in_span!(crate::trace::checkout(db.pool), db.cluster.checkout(db.pool));
+        metrics::checkout(served, start.elapsed());
+        let client = client?;
+        if !client.is_closed() {
+            return Ok(client);
+        }
+        debug!("Discard closed connection");
+        drop(Object::take(client));
     }
+}
 
+/// Times `query` for metrics and the slow-query log, and traces it when enabled.
+async fn observe<R: RowCount>(
+    db: PgHandle<'_>,
+    sql: Option<&str>,
+    params: &[&(dyn ToSql + Sync)],
+    query: impl Future<Output = Result<R, PgPoolError>>
+) -> Result<R, PgPoolError> {
+    let start = Instant::now();
+    let result = in_span!(crate::trace::query(db.pool, sql), timed(db.role().slow_log.as_ref(), db.pool, sql, params, query));
+    metrics::query(db.pool, start.elapsed(), &result);
     result
 }
 
+/// Asks the server to cancel whatever `client` is running.
+async fn cancel(client: &Client, timeout: Duration) {
+    let cancel = client.cancel_token();
+    match tokio::time::timeout(timeout, cancel.cancel_query(NoTls)).await {
+        Ok(Ok(())) => debug!("Cancelled query after {timeout:?}"),
+        Ok(Err(e)) => warn!("Cancel query failed: {e}"),
+        Err(_) => warn!("Cancel query timed out"),
+    }
+}
+
 pub fn close(pool: &Pool) {
     pool.close();
 }
 
+pub(crate) fn invalid_statement(err: &Error) -> bool {
+    match err.code() {
+        Some(&SqlState::UNDEFINED_PSTATEMENT) => true,
+        Some(&SqlState::FEATURE_NOT_SUPPORTED) => err.as_db_error()
+            .is_some_and(|e| e.message().contains("cached plan must not change result type")),
+        _ => false
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
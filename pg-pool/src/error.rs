@@ -0,0 +1,162 @@
+use deadpool_postgres::{
+    PoolError,
+    tokio_postgres::{
+        Error, Row,
+        error::{DbError, SqlState},
+        row::RowIndex,
+        types::FromSql,
+    }
+};
+use std::fmt;
+use std::time::Duration;
+
+/// Error returned by every `pg::*` / `pgr::*` function.
+#[derive(Debug)]
+pub enum PgPoolError {
+    /// Timed out waiting for, creating or recycling a pooled connection.
+    PoolTimeout(PoolError),
+    /// Pool is closed or misconfigured.
+    Pool(PoolError),
+    /// Connection could not be established or was lost.
+    Connection(Error),
+    /// Error reported by the server. See `code()` for its SqlState.
+    Sql(Error),
+    /// Failed to convert a column value into a Rust type.
+    Decode(Error),
+    /// Failed to read a column into a `FromRow` struct field.
+    Column {
+        field: &'static str,    // Struct.field
+        column: &'static str,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// Malformed named query or missing `:name` value.
+    Param(String),
+    /// Connection settings that cannot be used.
+    Config(String),
+    /// Invalid, modified or irreversible migration.
+    Migration(String),
+    /// Other client side failure, e.g. unexpected row count or parameter mismatch.
+    Query(Error),
+    /// Query ran past its timeout and was cancelled on the server.
+    QueryTimeout(Duration),
+    /// Operation aborted by the caller.
+    Cancelled,
+}
+
+impl PgPoolError {
+    /// SqlState of a server side error.
+    pub fn code(&self) -> Option<&SqlState> {
+        self.as_db_error().map(|e| e.code())
+    }
+
+    pub fn as_db_error(&self) -> Option<&DbError> {
+        match self {
+            PgPoolError::Sql(e) => e.as_db_error(),
+            _ => None
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, PgPoolError::PoolTimeout(_))
+    }
+
+    pub fn is_query_timeout(&self) -> bool {
+        matches!(self, PgPoolError::QueryTimeout(_))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        match self {
+            PgPoolError::Connection(e) => e.is_closed(),
+            _ => false
+        }
+    }
+}
+
+impl fmt::Display for PgPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgPoolError::PoolTimeout(e) => write!(f, "pg pool timeout: {e}"),
+            PgPoolError::Pool(e) => write!(f, "pg pool error: {e}"),
+            PgPoolError::Connection(e) => write!(f, "pg connection error: {e}"),
+            PgPoolError::Sql(e) => write!(f, "pg sql error: {e}"),
+            PgPoolError::Decode(e) => write!(f, "pg decode error: {e}"),
+            PgPoolError::Column { field, column, source } =>
+                write!(f, "pg decode error: field `{field}` from column `{column}`: {source}"),
+            PgPoolError::Param(e) => write!(f, "pg parameter error: {e}"),
+            PgPoolError::Config(e) => write!(f, "pg config error: {e}"),
+            PgPoolError::Migration(e) => write!(f, "pg migration error: {e}"),
+            PgPoolError::Query(e) => write!(f, "pg query error: {e}"),
+            PgPoolError::QueryTimeout(t) => write!(f, "pg query timeout after {t:?}"),
+            PgPoolError::Cancelled => write!(f, "pg operation cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for PgPoolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PgPoolError::PoolTimeout(e) | PgPoolError::Pool(e) => Some(e),
+            PgPoolError::Connection(e) | PgPoolError::Sql(e) |
+            PgPoolError::Decode(e) | PgPoolError::Query(e) => Some(e),
+            PgPoolError::Column { source, .. } => Some(source.as_ref()),
+            PgPoolError::Param(_) | PgPoolError::Config(_) | PgPoolError::Migration(_) |
+            PgPoolError::QueryTimeout(_) | PgPoolError::Cancelled => None,
+        }
+    }
+}
+
+impl From<PoolError> for PgPoolError {
+    fn from(e: PoolError) -> Self {
+        match e {
+            PoolError::Timeout(_) => PgPoolError::PoolTimeout(e),
+            PoolError::Backend(e) => PgPoolError::from(e),
+            e => PgPoolError::Pool(e),
+        }
+    }
+}
+
+impl From<Error> for PgPoolError {
+    fn from(e: Error) -> Self {
+        if e.as_db_error().is_some() {
+            PgPoolError::Sql(e)
+        } else if e.is_closed() || is_io_error(&e) {
+            PgPoolError::Connection(e)
+        } else {
+            PgPoolError::Query(e)
+        }
+    }
+}
+
+fn is_io_error(e: &Error) -> bool {
+    std::error::Error::source(e)
+        .is_some_and(|s| s.is::<std::io::Error>())
+}
+
+/// `Row::try_get` reporting conversion failures as `PgPoolError::Decode`.
+pub fn try_get<'a, I, T>(row: &'a Row, idx: I) -> Result<T, PgPoolError>
+where
+    I: RowIndex + fmt::Display,
+    T: FromSql<'a>,
+{
+    row.try_get(idx).map_err(PgPoolError::Decode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use deadpool_postgres::{TimeoutType, PoolError};
+
+    #[test]
+    fn it_classifies_pool_timeout() {
+        let err = PgPoolError::from(PoolError::Timeout(TimeoutType::Wait));
+        assert!(err.is_timeout());
+        assert_eq!(err.code(), None);
+    }
+
+    #[test]
+    fn it_classifies_closed_pool() {
+        let err = PgPoolError::from(PoolError::Closed);
+        assert!(matches!(err, PgPoolError::Pool(_)));
+        assert!(!err.is_timeout());
+    }
+}
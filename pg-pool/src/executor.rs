@@ -0,0 +1,260 @@
+use deadpool_postgres::{
+    Client,
+    tokio_postgres::{ToStatement, types::ToSql},
+};
+use crate::{PG_CLUSTER, PgHandle, PgPoolError, QueryText, Row, Transaction, Type, driver::{self, PgPool, with_cached}};
+use std::future::Future;
+
+/// Query surface shared by pool handles, pooled clients and transactions,
+/// so data-access code can be generic over where it runs.
+pub trait PgExecutor: Sync {
+    fn query<T>(
+        &self,
+        statement: &T,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<Vec<Row>, PgPoolError>> + Send
+    where
+        T: ?Sized + ToStatement + QueryText + Sync;
+
+    fn query_one<T>(
+        &self,
+        statement: &T,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<Row, PgPoolError>> + Send
+    where
+        T: ?Sized + ToStatement + QueryText + Sync;
+
+    fn query_opt<T>(
+        &self,
+        statement: &T,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<Option<Row>, PgPoolError>> + Send
+    where
+        T: ?Sized + ToStatement + QueryText + Sync;
+
+    fn execute<T>(
+        &self,
+        statement: &T,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<u64, PgPoolError>> + Send
+    where
+        T: ?Sized + ToStatement + QueryText + Sync;
+
+    fn query_pp(
+        &self,
+        query: &str,
+        types: &[Type],
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<Vec<Row>, PgPoolError>> + Send;
+
+    fn query_one_pp(
+        &self,
+        query: &str,
+        types: &[Type],
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<Row, PgPoolError>> + Send;
+
+    fn query_opt_pp(
+        &self,
+        query: &str,
+        types: &[Type],
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<Option<Row>, PgPoolError>> + Send;
+
+    fn execute_pp(
+        &self,
+        query: &str,
+        types: &[Type],
+        params: &[&(dyn ToSql + Sync)]
+    ) -> impl Future<Output = Result<u64, PgPoolError>> + Send;
+}
+
+impl PgExecutor for PgPool {
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::query(PG_CLUSTER.handle(*self), statement, params).await
+    }
+
+    async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::query_one(PG_CLUSTER.handle(*self), statement, params).await
+    }
+
+    async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::query_opt(PG_CLUSTER.handle(*self), statement, params).await
+    }
+
+    async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::execute(PG_CLUSTER.handle(*self), statement, params).await
+    }
+
+    async fn query_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError> {
+        driver::query_pp(PG_CLUSTER.handle(*self), query, types, params).await
+    }
+
+    async fn query_one_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError> {
+        driver::query_one_pp(PG_CLUSTER.handle(*self), query, types, params).await
+    }
+
+    async fn query_opt_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError> {
+        driver::query_opt_pp(PG_CLUSTER.handle(*self), query, types, params).await
+    }
+
+    async fn execute_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError> {
+        driver::execute_pp(PG_CLUSTER.handle(*self), query, types, params).await
+    }
+}
+
+impl PgExecutor for PgHandle<'_> {
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::query(*self, statement, params).await
+    }
+
+    async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::query_one(*self, statement, params).await
+    }
+
+    async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::query_opt(*self, statement, params).await
+    }
+
+    async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        driver::execute(*self, statement, params).await
+    }
+
+    async fn query_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError> {
+        driver::query_pp(*self, query, types, params).await
+    }
+
+    async fn query_one_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError> {
+        driver::query_one_pp(*self, query, types, params).await
+    }
+
+    async fn query_opt_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError> {
+        driver::query_opt_pp(*self, query, types, params).await
+    }
+
+    async fn execute_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError> {
+        driver::execute_pp(*self, query, types, params).await
+    }
+}
+
+impl PgExecutor for Client {
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).query(statement, params).await?)
+    }
+
+    async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).query_one(statement, params).await?)
+    }
+
+    async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).query_opt(statement, params).await?)
+    }
+
+    async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).execute(statement, params).await?)
+    }
+
+    async fn query_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError> {
+        Ok(with_cached!(self, query, types, |stmt| (**self).query(&stmt, params).await)?)
+    }
+
+    async fn query_one_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError> {
+        Ok(with_cached!(self, query, types, |stmt| (**self).query_one(&stmt, params).await)?)
+    }
+
+    async fn query_opt_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError> {
+        Ok(with_cached!(self, query, types, |stmt| (**self).query_opt(&stmt, params).await)?)
+    }
+
+    async fn execute_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError> {
+        Ok(with_cached!(self, query, types, |stmt| (**self).execute(&stmt, params).await)?)
+    }
+}
+
+// An invalidated statement aborts the transaction, so the `_pp` variants
+// cannot recover by re-preparing here.
+impl PgExecutor for Transaction<'_> {
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).query(statement, params).await?)
+    }
+
+    async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).query_one(statement, params).await?)
+    }
+
+    async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).query_opt(statement, params).await?)
+    }
+
+    async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        Ok((**self).execute(statement, params).await?)
+    }
+
+    async fn query_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError> {
+        let stmt = self.prepare_typed_cached(query, types).await?;
+        Ok((**self).query(&stmt, params).await?)
+    }
+
+    async fn query_one_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError> {
+        let stmt = self.prepare_typed_cached(query, types).await?;
+        Ok((**self).query_one(&stmt, params).await?)
+    }
+
+    async fn query_opt_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError> {
+        let stmt = self.prepare_typed_cached(query, types).await?;
+        Ok((**self).query_opt(&stmt, params).await?)
+    }
+
+    async fn execute_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError> {
+        let stmt = self.prepare_typed_cached(query, types).await?;
+        Ok((**self).execute(&stmt, params).await?)
+    }
+}
@@ -0,0 +1,445 @@
+//! In-memory `PgExecutor` for unit tests without Postgres.
+//!
+//! `FakeExecutor` drives a real `tokio_postgres` client against a scripted
+//! responder over an in-memory stream, so rows and `SqlState` errors are
+//! the genuine driver types. Transactions are not supported.
+//! Enabled by the `fake` feature, e.g. in `[dev-dependencies]`.
+
+use bytes::{BufMut, BytesMut};
+use deadpool_postgres::tokio_postgres::{
+    self, Config, NoTls, ToStatement,
+    config::SslMode,
+    error::SqlState,
+    types::{IsNull, ToSql},
+};
+use crate::{PgExecutor, PgPoolError, QueryText, Row, Type};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+/// Statement received by a `FakeExecutor`. Parameters are kept in their `Debug` form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Executed {
+    pub sql: String,
+    pub params: Vec<String>,
+}
+
+/// Scripted result set for `FakeExecutor::push_rows`.
+#[derive(Debug, Clone, Default)]
+pub struct FakeRows {
+    columns: Vec<(String, Type)>,
+    rows: Vec<Vec<Option<BytesMut>>>,
+}
+
+impl FakeRows {
+    pub fn new(columns: &[(&str, Type)]) -> Self {
+        Self {
+            columns: columns.iter().map(|(name, ty)| (name.to_string(), ty.clone())).collect(),
+            rows: vec![],
+        }
+    }
+
+    /// Appends a row. Panics if a value does not match its column type.
+    pub fn row(mut self, values: &[&(dyn ToSql + Sync)]) -> Self {
+        assert_eq!(values.len(), self.columns.len(), "FakeRows: column count mismatch");
+        let row = values.iter().zip(&self.columns)
+            .map(|(value, (name, ty))| {
+                let mut buf = BytesMut::new();
+                match value.to_sql_checked(ty, &mut buf) {
+                    Ok(IsNull::No) => Some(buf),
+                    Ok(IsNull::Yes) => None,
+                    Err(e) => panic!("FakeRows: cannot encode column {name}: {e}"),
+                }
+            })
+            .collect();
+        self.rows.push(row);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Response {
+    Rows(FakeRows),
+    Execute(u64),
+    Error(SqlState),
+}
+
+#[derive(Default)]
+struct FakeState {
+    responses: VecDeque<Response>,
+    executed: Vec<Executed>,
+    pending_params: Vec<String>,
+    pending_types: Vec<Type>,
+}
+
+/// Records executed SQL and replies with scripted responses in FIFO order.
+/// Without a scripted response, statements return no rows and affect 0 rows.
+pub struct FakeExecutor {
+    client: tokio_postgres::Client,
+    state: Arc<Mutex<FakeState>>,
+    op: tokio::sync::Mutex<()>,
+}
+
+impl FakeExecutor {
+    pub async fn new() -> Self {
+        let state = Arc::new(Mutex::new(FakeState::default()));
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        tokio::spawn(FakeServer::new(server_stream, state.clone()).run());
+
+        let (client, connection) = Config::new()
+            .user("fake")
+            .dbname("fake")
+            .ssl_mode(SslMode::Disable)
+            .connect_raw(client_stream, NoTls).await
+            .expect("FakeExecutor: handshake failed");
+        tokio::spawn(connection);
+
+        Self { client, state, op: tokio::sync::Mutex::new(()) }
+    }
+
+    pub fn push_rows(&self, rows: FakeRows) {
+        self.state.lock().unwrap().responses.push_back(Response::Rows(rows));
+    }
+
+    /// Scripts the affected row count of the next statement.
+    pub fn push_execute(&self, count: u64) {
+        self.state.lock().unwrap().responses.push_back(Response::Execute(count));
+    }
+
+    pub fn push_error(&self, code: SqlState) {
+        self.state.lock().unwrap().responses.push_back(Response::Error(code));
+    }
+
+    pub fn executed(&self) -> Vec<Executed> {
+        self.state.lock().unwrap().executed.clone()
+    }
+
+    pub fn sql(&self) -> Vec<String> {
+        self.executed().into_iter().map(|e| e.sql).collect()
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.responses.clear();
+        state.executed.clear();
+    }
+
+    async fn begin(&self, params: &[&(dyn ToSql + Sync)]) -> tokio::sync::MutexGuard<'_, ()> {
+        let guard = self.op.lock().await;
+        let mut state = self.state.lock().unwrap();
+        state.pending_params = params.iter().map(|p| format!("{p:?}")).collect();
+        state.pending_types = params.iter().map(|p| param_type(*p)).collect();
+        guard
+    }
+}
+
+impl PgExecutor for FakeExecutor {
+    async fn query<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        let _op = self.begin(params).await;
+        Ok(self.client.query(statement, params).await?)
+    }
+
+    async fn query_one<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        let _op = self.begin(params).await;
+        Ok(self.client.query_one(statement, params).await?)
+    }
+
+    async fn query_opt<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        let _op = self.begin(params).await;
+        Ok(self.client.query_opt(statement, params).await?)
+    }
+
+    async fn execute<T>(&self, statement: &T, params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError>
+    where
+        T: ?Sized + ToStatement + QueryText + Sync,
+    {
+        let _op = self.begin(params).await;
+        Ok(self.client.execute(statement, params).await?)
+    }
+
+    async fn query_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, PgPoolError> {
+        let _op = self.begin(params).await;
+        let stmt = self.client.prepare_typed(query, types).await?;
+        Ok(self.client.query(&stmt, params).await?)
+    }
+
+    async fn query_one_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Row, PgPoolError> {
+        let _op = self.begin(params).await;
+        let stmt = self.client.prepare_typed(query, types).await?;
+        Ok(self.client.query_one(&stmt, params).await?)
+    }
+
+    async fn query_opt_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<Option<Row>, PgPoolError> {
+        let _op = self.begin(params).await;
+        let stmt = self.client.prepare_typed(query, types).await?;
+        Ok(self.client.query_opt(&stmt, params).await?)
+    }
+
+    async fn execute_pp(&self, query: &str, types: &[Type], params: &[&(dyn ToSql + Sync)]) -> Result<u64, PgPoolError> {
+        let _op = self.begin(params).await;
+        let stmt = self.client.prepare_typed(query, types).await?;
+        Ok(self.client.execute(&stmt, params).await?)
+    }
+}
+
+// Types offered for parameters of untyped statements, in order of preference.
+const PARAM_TYPES: &[Type] = &[
+    Type::BOOL, Type::INT2, Type::INT4, Type::INT8, Type::FLOAT4, Type::FLOAT8,
+    Type::TEXT, Type::BYTEA, Type::TIMESTAMPTZ, Type::TIMESTAMP, Type::DATE, Type::TIME,
+    Type::UUID, Type::JSONB, Type::INET,
+    Type::BOOL_ARRAY, Type::INT2_ARRAY, Type::INT4_ARRAY, Type::INT8_ARRAY,
+    Type::FLOAT4_ARRAY, Type::FLOAT8_ARRAY, Type::TEXT_ARRAY, Type::BYTEA_ARRAY,
+    Type::UUID_ARRAY,
+];
+
+fn param_type(param: &(dyn ToSql + Sync)) -> Type {
+    PARAM_TYPES.iter()
+        .find(|ty| param.to_sql_checked(ty, &mut BytesMut::new()).is_ok())
+        .unwrap_or(&Type::TEXT)
+        .clone()
+}
+
+struct Prepared {
+    param_types: Vec<u32>,
+    response: Response,
+}
+
+struct FakeServer {
+    stream: DuplexStream,
+    state: Arc<Mutex<FakeState>>,
+    statements: HashMap<String, Prepared>,
+    portals: HashMap<String, String>,
+    failed: bool,  // Skip messages until Sync after an error
+}
+
+impl FakeServer {
+    fn new(stream: DuplexStream, state: Arc<Mutex<FakeState>>) -> Self {
+        Self { stream, state, statements: HashMap::new(), portals: HashMap::new(), failed: false }
+    }
+
+    async fn run(mut self) -> std::io::Result<()> {
+        // StartupMessage has no tag byte
+        let len = self.stream.read_i32().await?;
+        let mut startup = vec![0; len as usize - 4];
+        self.stream.read_exact(&mut startup).await?;
+
+        let mut out = BytesMut::new();
+        message(&mut out, b'R', |b| b.put_i32(0));
+        message(&mut out, b'K', |b| { b.put_i32(1); b.put_i32(0); });
+        message(&mut out, b'Z', |b| b.put_u8(b'I'));
+        self.stream.write_all(&out).await?;
+
+        loop {
+            let tag = self.stream.read_u8().await?;
+            let len = self.stream.read_i32().await?;
+            let mut body = vec![0; len as usize - 4];
+            self.stream.read_exact(&mut body).await?;
+            if tag == b'X' {
+                return Ok(());
+            }
+
+            let mut out = BytesMut::new();
+            self.handle(tag, &mut Reader(&body), &mut out);
+            if !out.is_empty() {
+                self.stream.write_all(&out).await?;
+            }
+        }
+    }
+
+    fn handle(&mut self, tag: u8, body: &mut Reader, out: &mut BytesMut) {
+        if self.failed && tag != b'S' {
+            return;
+        }
+        match tag {
+            b'P' => self.parse(body, out),
+            b'D' => self.describe(body, out),
+            b'B' => {
+                let portal = body.cstr();
+                let statement = body.cstr();
+                self.portals.insert(portal, statement);
+                message(out, b'2', |_| {});
+            }
+            b'E' => self.execute(body, out),
+            b'C' => {
+                let kind = body.u8();
+                let name = body.cstr();
+                if kind == b'S' {
+                    self.statements.remove(&name);
+                } else {
+                    self.portals.remove(&name);
+                }
+                message(out, b'3', |_| {});
+            }
+            b'Q' => {
+                let sql = body.cstr();
+                let response = self.record(sql);
+                match response {
+                    Response::Error(code) => error_response(out, &code),
+                    _ => message(out, b'C', |b| cstr(b, "SELECT 0")),
+                }
+                message(out, b'Z', |b| b.put_u8(b'I'));
+            }
+            b'S' => {
+                self.failed = false;
+                message(out, b'Z', |b| b.put_u8(b'I'));
+            }
+            _ => {}
+        }
+    }
+
+    fn parse(&mut self, body: &mut Reader, out: &mut BytesMut) {
+        let name = body.cstr();
+        let sql = body.cstr();
+        let oids: Vec<u32> = (0..body.i16()).map(|_| body.i32() as u32).collect();
+        let pending: Vec<u32> = self.state.lock().unwrap().pending_types.iter().map(|t| t.oid()).collect();
+        let param_types = (0..oids.len().max(pending.len()))
+            .map(|i| match oids.get(i) {
+                Some(&oid) if oid != 0 => oid,
+                _ => pending.get(i).copied().unwrap_or(Type::TEXT.oid()),
+            })
+            .collect();
+
+        let response = self.record(sql);
+        if let Response::Error(code) = &response {
+            error_response(out, code);
+            self.failed = true;
+            return;
+        }
+        self.statements.insert(name, Prepared { param_types, response });
+        message(out, b'1', |_| {});
+    }
+
+    fn record(&mut self, sql: String) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let params = std::mem::take(&mut state.pending_params);
+        state.pending_types.clear();
+        state.executed.push(Executed { sql, params });
+        state.responses.pop_front().unwrap_or(Response::Execute(0))
+    }
+
+    fn describe(&mut self, body: &mut Reader, out: &mut BytesMut) {
+        let kind = body.u8();
+        let name = body.cstr();
+        let name = if kind == b'P' { self.portals.get(&name).cloned().unwrap_or_default() } else { name };
+        let Some(prepared) = self.statements.get(&name) else {
+            error_response(out, &SqlState::INVALID_SQL_STATEMENT_NAME);
+            self.failed = true;
+            return;
+        };
+
+        if kind == b'S' {
+            message(out, b't', |b| {
+                b.put_i16(prepared.param_types.len() as i16);
+                prepared.param_types.iter().for_each(|oid| b.put_u32(*oid));
+            });
+        }
+        match &prepared.response {
+            Response::Rows(rows) => message(out, b'T', |b| {
+                b.put_i16(rows.columns.len() as i16);
+                for (name, ty) in &rows.columns {
+                    cstr(b, name);
+                    b.put_i32(0);      // table oid
+                    b.put_i16(0);      // column id
+                    b.put_u32(ty.oid());
+                    b.put_i16(-1);     // type size
+                    b.put_i32(-1);     // type modifier
+                    b.put_i16(1);      // binary format
+                }
+            }),
+            _ => message(out, b'n', |_| {}),
+        }
+    }
+
+    fn execute(&mut self, body: &mut Reader, out: &mut BytesMut) {
+        let portal = body.cstr();
+        let statement = self.portals.get(&portal).cloned().unwrap_or_default();
+        match self.statements.get(&statement).map(|p| &p.response) {
+            Some(Response::Rows(rows)) => {
+                for row in &rows.rows {
+                    message(out, b'D', |b| {
+                        b.put_i16(row.len() as i16);
+                        for value in row {
+                            match value {
+                                Some(v) => { b.put_i32(v.len() as i32); b.put_slice(v); }
+                                None => b.put_i32(-1),
+                            }
+                        }
+                    });
+                }
+                let tag = format!("SELECT {}", rows.rows.len());
+                message(out, b'C', |b| cstr(b, &tag));
+            }
+            Some(Response::Execute(count)) => {
+                let tag = format!("UPDATE {count}");
+                message(out, b'C', |b| cstr(b, &tag));
+            }
+            _ => {
+                error_response(out, &SqlState::INVALID_CURSOR_NAME);
+                self.failed = true;
+            }
+        }
+    }
+}
+
+fn message(out: &mut BytesMut, tag: u8, body: impl FnOnce(&mut BytesMut)) {
+    let mut buf = BytesMut::new();
+    body(&mut buf);
+    out.put_u8(tag);
+    out.put_i32(buf.len() as i32 + 4);
+    out.put_slice(&buf);
+}
+
+fn cstr(out: &mut BytesMut, s: &str) {
+    out.put_slice(s.as_bytes());
+    out.put_u8(0);
+}
+
+fn error_response(out: &mut BytesMut, code: &SqlState) {
+    message(out, b'E', |b| {
+        b.put_u8(b'S');
+        cstr(b, "ERROR");
+        b.put_u8(b'C');
+        cstr(b, code.code());
+        b.put_u8(b'M');
+        cstr(b, &format!("fake error {}", code.code()));
+        b.put_u8(0);
+    });
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn u8(&mut self) -> u8 {
+        let v = self.0[0];
+        self.0 = &self.0[1..];
+        v
+    }
+
+    fn i16(&mut self) -> i16 {
+        let v = i16::from_be_bytes([self.0[0], self.0[1]]);
+        self.0 = &self.0[2..];
+        v
+    }
+
+    fn i32(&mut self) -> i32 {
+        let v = i32::from_be_bytes([self.0[0], self.0[1], self.0[2], self.0[3]]);
+        self.0 = &self.0[4..];
+        v
+    }
+
+    fn cstr(&mut self) -> String {
+        let end = self.0.iter().position(|b| *b == 0).unwrap_or(self.0.len());
+        let s = String::from_utf8_lossy(&self.0[..end]).into_owned();
+        self.0 = &self.0[(end + 1).min(self.0.len())..];
+        s
+    }
+}
@@ -0,0 +1,68 @@
+use deadpool_postgres::Pool;
+use futures_util::future::try_join_all;
+use crate::{PG_CLUSTER, PgPoolError, check_config, driver::PgPool};
+use log::info;
+use server_conf::{SV_CONF, DbConf};
+use std::fmt;
+
+const PROBE: &str = "SELECT 1";
+
+/// Why `init` failed, and for which pool.
+#[derive(Debug)]
+pub enum InitError {
+    /// The pool could not be built from its config.
+    Config { pool: PgPool, message: String },
+    /// Opening a connection or running the probe query failed.
+    Connect { pool: PgPool, source: PgPoolError },
+}
+
+impl InitError {
+    pub fn pool(&self) -> PgPool {
+        match self {
+            InitError::Config { pool, .. } | InitError::Connect { pool, .. } => *pool,
+        }
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Config { pool, message } => write!(f, "pg {} pool config: {message}", pool.name()),
+            InitError::Connect { pool, source } => write!(f, "pg {} pool unreachable: {source}", pool.name()),
+        }
+    }
+}
+
+impl std::error::Error for InitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InitError::Config { .. } => None,
+            InitError::Connect { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Creates `PG_POOL` and `PGR_POOL`, opens `min_idle` connections in each
+/// (1 by default, at most `pool_max`) and runs a probe query on every one.
+/// Call it on startup so a bad config fails before serving requests.
+/// The reader is checked on its own, without falling back to the writer.
+pub async fn init() -> Result<(), InitError> {
+    // Checked first, as forcing the statics panics on a bad config
+    check_config(&SV_CONF.db).map_err(|message| InitError::Config { pool: PgPool::Writer, message })?;
+    if let Some(dbr) = SV_CONF.dbr.as_ref() {
+        check_config(dbr).map_err(|message| InitError::Config { pool: PgPool::Reader, message })?;
+    }
+    PG_CLUSTER.init().await
+}
+
+pub(crate) async fn warm_up(pool: PgPool, db: &DbConf, pg: &Pool) -> Result<(), InitError> {
+    let n = db.min_idle.unwrap_or(1).min(pg.status().max_size).max(1);
+    try_join_all((0..n).map(async |_| {
+        let client = pg.get().await?;
+        client.simple_query(PROBE).await?;
+        Ok::<_, PgPoolError>(client)
+    })).await.map_err(|source| InitError::Connect { pool, source })?;
+
+    info!("pg {} pool ready with {n} connections", pool.name());
+    Ok(())
+}
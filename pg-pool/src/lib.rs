@@ -1,19 +1,60 @@
+pub mod builder;
+#[cfg(feature = "fake")]
+pub mod fake;
+pub mod metrics;
+pub mod migrate;
 pub mod pg;
 pub mod pgr;
+pub mod queue;
+pub use cluster::{ClusterOptions, PgCluster, PgHandle};
+pub use copy::{CopyOptions, CopyOut, CopyRow};
+pub use credentials::{CredentialError, CredentialProvider, PasswordFile, PasswordFn, StaticPassword, set_credentials};
+pub use driver::PgPool;
+pub use error::{PgPoolError, try_get};
+pub use executor::PgExecutor;
+pub use init::{InitError, init};
+pub use listen::{ListenEvent, Listener, listen};
+pub use lock::{AdvisoryLock, Leader, lock_key};
+pub use metrics::status;
+pub use named::NamedParams;
+pub use quote::{quote_ident, quote_literal, quote_literals, quote_qualified, text_array};
+pub use retry::{RetryPolicy, is_transient, retry};
+pub use row::FromRow;
+pub use slow_query::QueryText;
+pub use pg_pool_derive::{FromRow, NamedParams, embed_migrations};
+#[doc(hidden)]
+pub use row::private as __private;
+pub use stream::QueryStream;
+pub use transaction::{Transaction, TxOptions};
 pub use deadpool_postgres::{
     Pool, PoolError,
-    tokio_postgres::{Error, Row, Statement, types::Type}
+    tokio_postgres::{Error, IsolationLevel, Notification, Row, Statement, binary_copy::BinaryCopyOutRow, types::Type}
 };
 
 use log::error;
-use deadpool_postgres::{
-    Config, ManagerConfig, RecyclingMethod, Runtime, Timeouts,
-    tokio_postgres::{NoTls}
-};
+use credentials::Connector;
+use deadpool_postgres::{Config, Manager, ManagerConfig, RecyclingMethod, Runtime, Timeouts};
 use server_conf::{SV_CONF, DbConf};
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
+mod cluster;
+mod copy;
+mod credentials;
 mod driver;
+mod error;
+mod executor;
+mod init;
+mod listen;
+mod lock;
+mod named;
+mod quote;
+mod random;
+mod retry;
+mod row;
+mod slow_query;
+mod stream;
+mod trace;
+mod transaction;
 
 pub static PG_POOL: LazyLock<Pool> = LazyLock::new(|| create_pool(&SV_CONF.db).unwrap());
 
@@ -25,13 +66,48 @@ pub static PGR_POOL: LazyLock<Option<Pool>> = LazyLock::new(|| {
     }
 });
 
+// Cluster behind `pg::*`, `pgr::*` and `PgPool` executors
+pub static PG_CLUSTER: LazyLock<PgCluster> = LazyLock::new(|| {
+    let readers = PGR_POOL.clone().zip(SV_CONF.dbr.clone()).into_iter().collect();
+    let options = ClusterOptions { fallback: default_fallback(), ..Default::default() };
+    PgCluster::from_pools((PG_POOL.clone(), SV_CONF.db.clone()), readers, options)
+});
+
 pub fn create_pool(db: &DbConf) -> Result<Pool, String> {
+    build_pool(db, default_fallback(), None)
+}
+
+fn default_fallback() -> bool {
+    SV_CONF.dbr.as_ref().map(|dbr| dbr.fallback ).unwrap_or(false)
+}
+
+// With reads falling back to the writer, connect and recycle are bounded too
+fn build_pool(db: &DbConf, fallback: bool, credentials: Option<Arc<dyn CredentialProvider>>) -> Result<Pool, String> {
     let pool_max: usize = db.pool_max.unwrap_or(1);
-    let timeouts = match SV_CONF.dbr.as_ref().map(|dbr| dbr.fallback ).unwrap_or(false) {
+    let timeouts = match fallback {
         true => timeouts_object(db.timeout.unwrap_or(500), 900, 1500),
         false => Timeouts::wait_millis(db.timeout.unwrap_or(500))
     };
 
+    let pg_config = check_config(db)?;
+    // The connector sets the password for every new connection
+    let connector = Connector::new(db, pg_config.clone(), credentials);
+    let manager = Manager::from_connect(
+        pg_config, connector, ManagerConfig { recycling_method: RecyclingMethod::Fast }
+    );
+    // NOTE: Runtime is also configurable.
+    Pool::builder(manager)
+        .max_size(pool_max)
+        .timeouts(timeouts)
+        .runtime(Runtime::Tokio1)
+        .build()
+        .map_err(|e| {
+            error!("{}", e);
+            format!("Cannot build pg pool: {e}")
+        })
+}
+
+fn db_config(db: &DbConf) -> Config {
     let mut cfg = Config::new();
     cfg.dbname = Some(db.name.clone());
     if db.hosts.is_some() {
@@ -42,23 +118,38 @@ pub fn create_pool(db: &DbConf) -> Result<Pool, String> {
     cfg.port = Some(db.port);
     cfg.user = Some(db.user.clone());
     cfg.password = Some(db.password.clone());
-    // NOTE: Runtime is also configurable.
-    cfg.manager = Some(ManagerConfig { recycling_method: RecyclingMethod::Fast });
-    cfg.builder(NoTls)
+    cfg
+}
+
+// Connection settings for `db`, checked without building a pool
+fn check_config(db: &DbConf) -> Result<deadpool_postgres::tokio_postgres::Config, String> {
+    if db.pool_max == Some(0) {
+        error!("pool_max is 0 for {}", target(db));
+        return Err("pool_max must be at least 1".to_string());
+    }
+    db_config(db).get_pg_config()
         .map_err(|e| {
-            error!("{} {:?}", e,  SV_CONF.db);
+            error!("{} for {}", e, target(db));
             format!("Cannot process pg config: {e}")
-        })?
-        .max_size(pool_max)
-        .timeouts(timeouts)
-        .runtime(Runtime::Tokio1)
-        .build()
-        .map_err(|e| {
-            error!("{}", e);
-            format!("Cannot build pg pool: {e}")
         })
 }
 
+// Server and database of `db` for logs, leaving out the credentials
+fn target(db: &DbConf) -> String {
+    let host = match &db.hosts {
+        Some(hosts) => hosts.join(","),
+        None => db.host.clone(),
+    };
+    format!("{host}:{}/{}", db.port, db.name)
+}
+
+// Connections opened outside the pool
+fn connector(db: &DbConf, credentials: Option<Arc<dyn CredentialProvider>>) -> Result<Connector, PgPoolError> {
+    let config = db_config(db).get_pg_config()
+        .map_err(|e| PgPoolError::Config(format!("Cannot process pg config: {e}")))?;
+    Ok(Connector::new(db, config, credentials))
+}
+
 fn timeouts_object(wait: u64, create: u64, recycle: u64) -> Timeouts {
     let mut timeouts = Timeouts::new();
     timeouts.wait = Some(Duration::from_millis(wait));
@@ -0,0 +1,154 @@
+use deadpool_postgres::tokio_postgres::{
+    AsyncMessage, Client, Connection, Error, NoTls, Notification, Socket,
+    tls::NoTlsStream,
+};
+use futures_util::{Stream, future::{self, Either}, pin_mut};
+use crate::{CredentialProvider, PG_CLUSTER, PgPoolError, connector, credentials::Connector, quote::quote_ident, retry::RetryPolicy};
+use log::{debug, warn};
+use server_conf::DbConf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+#[derive(Debug)]
+pub enum ListenEvent {
+    Notification(Notification),
+    /// The connection was lost and re-established. Notifications sent in
+    /// between were missed, so consumers should resync their state.
+    Gap,
+}
+
+/// Notifications received on a dedicated connection. Reconnects and
+/// re-issues `LISTEN` until dropped.
+pub struct Listener {
+    rx: UnboundedReceiver<ListenEvent>,
+}
+
+impl Stream for Listener {
+    type Item = ListenEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+type Conn = Connection<Socket, NoTlsStream>;
+
+/// Subscribes to `channels` on a connection to `db`, outside the pool.
+/// Channel names are quoted, so they match `pg_notify` and quoted `NOTIFY` exactly.
+pub async fn listen(channels: &[&str]) -> Result<Listener, PgPoolError> {
+    PG_CLUSTER.listen(channels).await
+}
+
+pub(crate) async fn subscribe(
+    db: &DbConf,
+    credentials: Option<Arc<dyn CredentialProvider>>,
+    channels: &[&str]
+) -> Result<Listener, PgPoolError> {
+    let connector = connector(db, credentials)?;
+    let sql = channels.iter()
+        .map(|c| format!("LISTEN {};", quote_ident(c)))
+        .collect::<String>();
+
+    let (tx, rx) = unbounded_channel();
+    let (client, conn) = connect(&connector, &sql, &tx).await?;
+    tokio::spawn(run(connector, RetryPolicy::from_conf(db), sql, client, conn, tx));
+    Ok(Listener { rx })
+}
+
+async fn run(
+    connector: Connector,
+    policy: RetryPolicy,
+    sql: String,
+    mut client: Client,
+    mut conn: Conn,
+    tx: UnboundedSender<ListenEvent>
+) {
+    loop {
+        match pump(&mut conn, &tx).await {
+            Ended::Lost(Some(err)) => warn!("Listener connection lost: {err}"),
+            Ended::Lost(None) => warn!("Listener connection closed"),
+            Ended::Dropped => return,
+        }
+        drop(client);
+
+        let mut retry = 0;
+        (client, conn) = loop {
+            let sleep = tokio::time::sleep(policy.wait(retry));
+            let closed = tx.closed();
+            pin_mut!(sleep, closed);
+            if let Either::Right(_) = future::select(sleep, closed).await {
+                return;
+            }
+            match connect(&connector, &sql, &tx).await {
+                Ok(c) => break c,
+                Err(e) => {
+                    debug!("Listener reconnect failed: {e}");
+                    retry = retry.saturating_add(1);
+                }
+            }
+        };
+        if tx.send(ListenEvent::Gap).is_err() {
+            return;
+        }
+    }
+}
+
+/// Connects and issues `LISTEN`, forwarding anything received meanwhile.
+async fn connect(connector: &Connector, sql: &str, tx: &UnboundedSender<ListenEvent>) -> Result<(Client, Conn), PgPoolError> {
+    let (client, mut conn) = connector.config().await.connect(NoTls).await?;
+    {
+        let listen = client.batch_execute(sql);
+        pin_mut!(listen);
+        let ended = future::poll_fn(|cx| {
+            if let Poll::Ready(result) = listen.as_mut().poll(cx) {
+                return Poll::Ready(Ok(result));
+            }
+            poll_conn(&mut conn, tx, cx).map(Err)
+        }).await;
+        match ended {
+            Ok(result) => result?,
+            Err(Ended::Lost(Some(e))) => return Err(e.into()),
+            Err(_) => {
+                // Without its connection the pending LISTEN fails as closed
+                drop(conn);
+                return Err(listen.await.err().map_or(PgPoolError::Cancelled, Into::into));
+            }
+        }
+    }
+    Ok((client, conn))
+}
+
+enum Ended {
+    Lost(Option<Error>),
+    Dropped,
+}
+
+/// Drives the connection until it ends or the `Listener` is dropped.
+async fn pump(conn: &mut Conn, tx: &UnboundedSender<ListenEvent>) -> Ended {
+    let closed = tx.closed();
+    pin_mut!(closed);
+    future::poll_fn(|cx| {
+        if closed.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Ended::Dropped);
+        }
+        poll_conn(conn, tx, cx)
+    }).await
+}
+
+fn poll_conn(conn: &mut Conn, tx: &UnboundedSender<ListenEvent>, cx: &mut Context<'_>) -> Poll<Ended> {
+    loop {
+        match conn.poll_message(cx) {
+            Poll::Ready(Some(Ok(AsyncMessage::Notification(n)))) => {
+                if tx.send(ListenEvent::Notification(n)).is_err() {
+                    return Poll::Ready(Ended::Dropped);
+                }
+            }
+            Poll::Ready(Some(Ok(_))) => {}
+            Poll::Ready(Some(Err(e))) => return Poll::Ready(Ended::Lost(Some(e))),
+            Poll::Ready(None) => return Poll::Ready(Ended::Lost(None)),
+            Poll::Pending => return Poll::Pending,
+        }
+    }
+}
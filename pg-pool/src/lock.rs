@@ -0,0 +1,204 @@
+use deadpool_postgres::{
+    Client, Object,
+    tokio_postgres::{self, Error, NoTls},
+};
+use crate::{CredentialProvider, PgHandle, PgPoolError, connector, credentials::Connector, driver};
+use server_conf::DbConf;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Session advisory lock. Keeps its pooled client checked out and unlocks
+/// when dropped; if that fails the connection is discarded, which also unlocks.
+pub struct AdvisoryLock {
+    client: Option<Client>,
+    key: i64,
+}
+
+impl AdvisoryLock {
+    pub fn key(&self) -> i64 {
+        self.key
+    }
+
+    /// The connection holding the lock.
+    pub fn client(&self) -> &Client {
+        self.client.as_ref().unwrap()
+    }
+
+    pub async fn unlock(mut self) -> Result<(), PgPoolError> {
+        let client = self.client.take().unwrap();
+        match client.execute("SELECT pg_advisory_unlock($1)", &[&self.key]).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                drop(Object::take(client));
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Client of a lock call in flight. When the call is dropped the query is
+/// cancelled and the connection discarded, so the session can neither take
+/// the lock later nor go back to the pool holding it.
+pub(crate) struct Pending(Option<Client>);
+
+impl Pending {
+    pub(crate) fn new(client: Client) -> Self {
+        Self(Some(client))
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        self.0.as_ref().unwrap()
+    }
+
+    pub(crate) fn client_mut(&mut self) -> &mut Client {
+        self.0.as_mut().unwrap()
+    }
+
+    pub(crate) fn done(mut self) -> Client {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let Some(client) = self.0.take() else { return };
+        let cancel = client.cancel_token();
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move { cancel.cancel_query(NoTls).await });
+        }
+        drop(Object::take(client));
+    }
+}
+
+impl Drop for AdvisoryLock {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else { return };
+        let key = self.key;
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if client.execute("SELECT pg_advisory_unlock($1)", &[&key]).await.is_err() {
+                        drop(Object::take(client));
+                    }
+                });
+            }
+            Err(_) => drop(Object::take(client)),
+        }
+    }
+}
+
+/// Lock key for a name, stable across builds and processes.
+pub fn lock_key(name: &str) -> i64 {
+    fnv1a(name.as_bytes()) as i64
+}
+
+// Stable across builds, unlike std's hashers
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+pub(crate) async fn advisory_lock(db: PgHandle<'_>, key: i64) -> Result<AdvisoryLock, PgPoolError> {
+    let pending = Pending::new(driver::get(db).await?);
+    pending.client().execute("SELECT pg_advisory_lock($1)", &[&key]).await?;
+    Ok(AdvisoryLock { client: Some(pending.done()), key })
+}
+
+pub(crate) async fn try_advisory_lock(db: PgHandle<'_>, key: i64) -> Result<Option<AdvisoryLock>, PgPoolError> {
+    let pending = Pending::new(driver::get(db).await?);
+    let locked: bool = pending.client().query_one("SELECT pg_try_advisory_lock($1)", &[&key]).await?.get(0);
+    Ok(locked.then(|| AdvisoryLock { client: Some(pending.done()), key }))
+}
+
+/// Leader election over a session advisory lock on a dedicated connection.
+/// Every `interval` a follower tries to take the lock and the leader checks
+/// its connection, reconnecting when it is lost. Dropping `Leader` closes the
+/// connection, which resigns.
+pub struct Leader {
+    state: watch::Receiver<bool>,
+    task: JoinHandle<()>,
+}
+
+impl Leader {
+    pub fn is_leader(&self) -> bool {
+        *self.state.borrow()
+    }
+
+    /// Leadership changes. Another instance may take over up to `interval`
+    /// before this one notices its connection is gone.
+    pub fn watch(&self) -> watch::Receiver<bool> {
+        self.state.clone()
+    }
+}
+
+impl Drop for Leader {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+pub(crate) fn elect(
+    db: &DbConf,
+    credentials: Option<Arc<dyn CredentialProvider>>,
+    key: i64,
+    interval: Duration
+) -> Result<Leader, PgPoolError> {
+    let connector = connector(db, credentials)?;
+    let (tx, state) = watch::channel(false);
+    let task = tokio::spawn(campaign(connector, key, interval, tx));
+    Ok(Leader { state, task })
+}
+
+async fn campaign(connector: Connector, key: i64, interval: Duration, tx: watch::Sender<bool>) {
+    loop {
+        match connector.config().await.connect(NoTls).await {
+            Ok((client, conn)) => {
+                let conn = tokio::spawn(conn);
+                if let Err(e) = lead(&client, key, interval, &tx).await {
+                    warn!("Leader connection for lock {key} lost: {e}");
+                }
+                tx.send_if_modified(|leader| std::mem::replace(leader, false));
+                // A hung connection may still hold the lock until the server notices
+                conn.abort();
+            }
+            Err(e) => warn!("Leader connection for lock {key} failed: {e}"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Competes for `key` until the connection fails or does not answer within `interval`.
+async fn lead(client: &tokio_postgres::Client, key: i64, interval: Duration, tx: &watch::Sender<bool>) -> Result<(), PgPoolError> {
+    loop {
+        let leading = *tx.borrow();
+        let check = async {
+            if leading {
+                client.simple_query("SELECT 1").await?;
+                Ok::<_, Error>(false)
+            } else {
+                Ok(client.query_one("SELECT pg_try_advisory_lock($1)", &[&key]).await?.get(0))
+            }
+        };
+        let elected = tokio::time::timeout(interval, check).await
+            .map_err(|_| PgPoolError::QueryTimeout(interval))??;
+        if elected {
+            info!("Elected leader for lock {key}");
+            tx.send_replace(true);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_keys_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(lock_key("a"), 0xaf63dc4c8601ec8cu64 as i64);
+    }
+}
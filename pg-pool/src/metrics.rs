@@ -0,0 +1,305 @@
+//! Pool status and counters for `PG_POOL` and `PGR_POOL`.
+//!
+//! Counters cover calls through `pg::*`, `pgr::*`, `PgPool` executors and
+//! `PgCluster` handles, summed over every cluster;
+//! queries run directly on a checked out `Client` or `Transaction` are not
+//! counted. Render everything in Prometheus text format with `prometheus()`
+//! (feature `prometheus`).
+use crate::{PG_CLUSTER, PgPoolError, driver::PgPool};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Upper bounds of histogram buckets, in seconds.
+pub const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolStatus {
+    pub size: usize,        // Open connections
+    pub available: usize,   // Idle connections
+    pub waiting: usize,     // Callers waiting for a connection
+    pub max: usize,
+}
+
+impl From<deadpool_postgres::Status> for PoolStatus {
+    fn from(s: deadpool_postgres::Status) -> Self {
+        Self {
+            size: s.size,
+            available: s.available,
+            waiting: s.waiting,
+            max: s.max_size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub writer: PoolStatus,
+    pub reader: Option<PoolStatus>,   // None without `dbr`
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterStatus {
+    pub writer: PoolStatus,
+    pub readers: Vec<PoolStatus>,
+}
+
+/// Current size of each pool. Creates the pools if needed.
+pub fn status() -> Status {
+    let status = PG_CLUSTER.status();
+    Status {
+        writer: status.writer,
+        reader: status.readers.first().copied(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<(f64, u64)>,   // Upper bound in seconds, cumulative count
+    pub count: u64,
+    pub sum: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolMetrics {
+    pub checkout_wait: HistogramSnapshot,
+    pub query_latency: HistogramSnapshot,
+    pub errors: BTreeMap<String, u64>,   // By SqlState, or error kind for client side errors
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    pub writer: PoolMetrics,
+    pub reader: PoolMetrics,
+    pub fallbacks: u64,       // Reads served by the writer because the reader failed
+    pub cache_hits: u64,      // Statements found in the statement cache
+    pub cache_misses: u64,
+}
+
+pub fn metrics() -> Metrics {
+    let pool = |c: &Counters| PoolMetrics {
+        checkout_wait: c.checkout_wait.snapshot(),
+        query_latency: c.query_latency.snapshot(),
+        errors: c.errors.lock().unwrap().clone(),
+    };
+    Metrics {
+        writer: pool(&COUNTERS[PgPool::Writer as usize]),
+        reader: pool(&COUNTERS[PgPool::Reader as usize]),
+        fallbacks: FALLBACKS.load(Ordering::Relaxed),
+        cache_hits: CACHE_HITS.load(Ordering::Relaxed),
+        cache_misses: CACHE_MISSES.load(Ordering::Relaxed),
+    }
+}
+
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let mut total = 0;
+        let buckets = BUCKETS.iter().zip(&self.buckets)
+            .map(|(le, n)| {
+                total += n.load(Ordering::Relaxed);
+                (*le, total)
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum: Duration::from_micros(self.sum_micros.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+struct Counters {
+    checkout_wait: Histogram,
+    query_latency: Histogram,
+    errors: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            checkout_wait: Histogram::new(),
+            query_latency: Histogram::new(),
+            errors: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+// Indexed by `PgPool as usize`
+static COUNTERS: [Counters; 2] = [Counters::new(), Counters::new()];
+static FALLBACKS: AtomicU64 = AtomicU64::new(0);
+static CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn checkout(pool: PgPool, wait: Duration) {
+    COUNTERS[pool as usize].checkout_wait.observe(wait);
+}
+
+pub(crate) fn query<T>(pool: PgPool, elapsed: Duration, result: &Result<T, PgPoolError>) {
+    let counters = &COUNTERS[pool as usize];
+    counters.query_latency.observe(elapsed);
+    if let Err(e) = result {
+        *counters.errors.lock().unwrap().entry(error_label(e)).or_default() += 1;
+    }
+}
+
+pub(crate) fn fallback() {
+    FALLBACKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn cache(hit: bool) {
+    match hit {
+        true => CACHE_HITS.fetch_add(1, Ordering::Relaxed),
+        false => CACHE_MISSES.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+fn error_label(e: &PgPoolError) -> String {
+    match e {
+        PgPoolError::PoolTimeout(_) => "pool_timeout",
+        PgPoolError::Pool(_) => "pool",
+        PgPoolError::Connection(_) => "connection",
+        PgPoolError::Sql(_) => return e.code().map_or("sql", |c| c.code()).to_string(),
+        PgPoolError::Decode(_) | PgPoolError::Column { .. } => "decode",
+        PgPoolError::Param(_) => "param",
+        PgPoolError::Config(_) => "config",
+        PgPoolError::Migration(_) => "migration",
+        PgPoolError::Query(_) => "query",
+        PgPoolError::QueryTimeout(_) => "query_timeout",
+        PgPoolError::Cancelled => "cancelled",
+    }.to_string()
+}
+
+/// Status and metrics in Prometheus text exposition format.
+#[cfg(feature = "prometheus")]
+pub fn prometheus() -> String {
+    render(&status(), &metrics())
+}
+
+#[cfg(feature = "prometheus")]
+fn render(status: &Status, metrics: &Metrics) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let pools = [(PgPool::Writer, Some(status.writer), &metrics.writer), (PgPool::Reader, status.reader, &metrics.reader)];
+
+    for (name, help, value) in [
+        ("pg_pool_size", "Open connections.", (|s: &PoolStatus| s.size) as fn(&PoolStatus) -> usize),
+        ("pg_pool_available", "Idle connections.", |s| s.available),
+        ("pg_pool_waiting", "Callers waiting for a connection.", |s| s.waiting),
+        ("pg_pool_max_size", "Maximum connections.", |s| s.max),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+        for (pool, status, _) in &pools {
+            if let Some(status) = status {
+                let _ = writeln!(out, "{name}{{pool=\"{}\"}} {}", pool.name(), value(status));
+            }
+        }
+    }
+
+    for (name, help, histogram) in [
+        ("pg_pool_checkout_wait_seconds", "Time waiting for a pooled connection.",
+            (|m: &PoolMetrics| &m.checkout_wait) as fn(&PoolMetrics) -> &HistogramSnapshot),
+        ("pg_pool_query_duration_seconds", "Statement latency including checkout and retries.", |m| &m.query_latency),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        for (pool, _, metrics) in &pools {
+            let h = histogram(metrics);
+            let pool = pool.name();
+            for (le, n) in &h.buckets {
+                let _ = writeln!(out, "{name}_bucket{{pool=\"{pool}\",le=\"{le}\"}} {n}");
+            }
+            let _ = writeln!(out, "{name}_bucket{{pool=\"{pool}\",le=\"+Inf\"}} {}", h.count);
+            let _ = writeln!(out, "{name}_sum{{pool=\"{pool}\"}} {}", h.sum.as_secs_f64());
+            let _ = writeln!(out, "{name}_count{{pool=\"{pool}\"}} {}", h.count);
+        }
+    }
+
+    let _ = writeln!(out, "# HELP pg_pool_errors_total Failed statements by SqlState or error kind.");
+    let _ = writeln!(out, "# TYPE pg_pool_errors_total counter");
+    for (pool, _, metrics) in &pools {
+        for (code, n) in &metrics.errors {
+            let _ = writeln!(out, "pg_pool_errors_total{{pool=\"{}\",code=\"{code}\"}} {n}", pool.name());
+        }
+    }
+
+    for (name, help, value) in [
+        ("pg_pool_fallbacks_total", "Reads served by the writer after the reader failed.", metrics.fallbacks),
+        ("pg_pool_statement_cache_hits_total", "Statements found in the statement cache.", metrics.cache_hits),
+        ("pg_pool_statement_cache_misses_total", "Statements prepared for the statement cache.", metrics.cache_misses),
+    ] {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_fills_cumulative_buckets() {
+        let h = Histogram::new();
+        h.observe(Duration::from_micros(500));
+        h.observe(Duration::from_millis(30));
+        h.observe(Duration::from_secs(60));
+        let s = h.snapshot();
+        assert_eq!(s.count, 3);
+        assert_eq!(s.buckets[0], (0.001, 1));
+        assert_eq!(s.buckets[3], (0.025, 1));
+        assert_eq!(s.buckets[4], (0.05, 2));
+        assert_eq!(s.buckets.last(), Some(&(10.0, 2)));
+        assert_eq!(s.sum, Duration::from_micros(60_030_500));
+    }
+
+    #[test]
+    fn it_labels_errors() {
+        assert_eq!(error_label(&PgPoolError::QueryTimeout(Duration::ZERO)), "query_timeout");
+        assert_eq!(error_label(&PgPoolError::Param("x".to_string())), "param");
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn it_renders_prometheus_text() {
+        let pool = PoolStatus { size: 1, available: 1, waiting: 0, max: 1 };
+        let counters = Counters::new();
+        counters.query_latency.observe(Duration::from_millis(3));
+        let snapshot = PoolMetrics {
+            checkout_wait: counters.checkout_wait.snapshot(),
+            query_latency: counters.query_latency.snapshot(),
+            errors: BTreeMap::from([("57014".to_string(), 2)]),
+        };
+        let metrics = Metrics { writer: snapshot.clone(), reader: snapshot, fallbacks: 0, cache_hits: 4, cache_misses: 1 };
+        let text = render(&Status { writer: pool, reader: None }, &metrics);
+        assert!(text.contains("# TYPE pg_pool_query_duration_seconds histogram\n"));
+        assert!(text.contains("pg_pool_query_duration_seconds_bucket{pool=\"writer\",le=\"+Inf\"}"));
+        assert!(text.contains("pg_pool_max_size{pool=\"writer\"} 1\n"));
+        assert!(!text.contains("pg_pool_size{pool=\"reader\"}"));
+        assert!(text.contains("pg_pool_query_duration_seconds_bucket{pool=\"writer\",le=\"0.005\"} 1\n"));
+        assert!(text.contains("pg_pool_errors_total{pool=\"writer\",code=\"57014\"} 2\n"));
+        assert!(text.contains("pg_pool_statement_cache_hits_total 4\n"));
+    }
+}
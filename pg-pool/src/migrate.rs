@@ -0,0 +1,272 @@
+//! Versioned SQL migrations applied to `PG_POOL`.
+//!
+//! Files are named `<version>_<name>.sql`, with an optional
+//! `<version>_<name>.down.sql` to revert them. Embed a directory with
+//! `pg_pool::embed_migrations!("migrations")` or load one with `Migrator::from_dir`.
+//! Each migration runs in its own transaction under a session advisory lock,
+//! so only one process migrates at a time.
+use deadpool_postgres::{Client, Object};
+use crate::{PG_CLUSTER, PgPoolError, Row, driver, lock::{Pending, fnv1a, lock_key}, quote::quote_qualified};
+use log::{info, warn};
+use std::borrow::Cow;
+use std::path::Path;
+
+mod file_name;
+pub use file_name::parse_file_name;
+use file_name::duplicate_version;
+
+const DEFAULT_TABLE: &str = "pg_pool_migrations";
+
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub version: i64,
+    pub name: Cow<'static, str>,
+    pub up: Cow<'static, str>,
+    pub down: Option<Cow<'static, str>>,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        format!("{:016x}", fnv1a(self.up.as_bytes()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Applied,
+    Pending,
+    /// Applied, but the file changed since. Fix the file or `repair`.
+    Modified,
+    /// Applied, but the file no longer exists.
+    Missing,
+}
+
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub version: i64,
+    pub name: String,
+    pub state: State,
+}
+
+pub struct Migrator {
+    migrations: Vec<Migration>,
+    table: String,
+}
+
+impl Migrator {
+    pub fn new(mut migrations: Vec<Migration>) -> Self {
+        migrations.sort_by_key(|m| m.version);
+        Self { migrations, table: DEFAULT_TABLE.to_string() }
+    }
+
+    /// Reads `.sql` files from `dir` at runtime.
+    pub fn from_dir(dir: impl AsRef<Path>) -> Result<Self, PgPoolError> {
+        let mut ups = Vec::new();
+        let mut downs = Vec::new();
+        let entries = std::fs::read_dir(dir.as_ref())
+            .map_err(|e| PgPoolError::Migration(format!("{}: {e}", dir.as_ref().display())))?;
+        for entry in entries {
+            let path = entry.map_err(|e| PgPoolError::Migration(e.to_string()))?.path();
+            let Some(file) = path.file_name().and_then(|f| f.to_str()) else { continue };
+            let Some((version, name, down)) = parse_file_name(file) else { continue };
+            let sql = std::fs::read_to_string(&path)
+                .map_err(|e| PgPoolError::Migration(format!("{}: {e}", path.display())))?;
+            if down {
+                downs.push((version, sql));
+            } else {
+                ups.push((version, name.to_string(), sql));
+            }
+        }
+        if let Some(version) = duplicate_version(
+            ups.iter().map(|(v, ..)| (*v, false)).chain(downs.iter().map(|(v, _)| (*v, true)))
+        ) {
+            return Err(PgPoolError::Migration(format!("duplicate migration version {version} in {}", dir.as_ref().display())));
+        }
+        let migrations = ups.into_iter()
+            .map(|(version, name, up)| Migration {
+                version,
+                name: name.into(),
+                up: up.into(),
+                down: downs.iter().find(|(v, _)| *v == version).map(|(_, sql)| sql.clone().into()),
+            })
+            .collect();
+        Ok(Self::new(migrations))
+    }
+
+    /// History table, `pg_pool_migrations` by default. Also keys the advisory lock.
+    pub fn table(mut self, table: &str) -> Self {
+        self.table = table.to_string();
+        self
+    }
+
+    pub fn migrations(&self) -> &[Migration] {
+        &self.migrations
+    }
+
+    /// Applies pending migrations in version order. Returns the applied versions.
+    /// Fails without applying anything if an applied migration was modified.
+    pub async fn up(&self) -> Result<Vec<i64>, PgPoolError> {
+        if let Some(version) = duplicate_version(self.migrations.iter().map(|m| (m.version, false))) {
+            return Err(PgPoolError::Migration(format!("duplicate migration version {version}")));
+        }
+        self.locked(async |client| {
+            let status = self.load_status(client).await?;
+            if let Some(s) = status.iter().find(|s| s.state == State::Modified) {
+                return Err(PgPoolError::Migration(format!("migration {} {} was modified after it was applied", s.version, s.name)));
+            }
+            let mut applied = Vec::new();
+            for m in &self.migrations {
+                if status.iter().any(|s| s.version == m.version && s.state == State::Pending) {
+                    info!("Apply migration {} {}", m.version, m.name);
+                    self.apply(client, m, &m.up, true).await?;
+                    applied.push(m.version);
+                }
+            }
+            Ok(applied)
+        }).await
+    }
+
+    /// Read only: takes no lock, and reports everything pending without a history table.
+    pub async fn status(&self) -> Result<Vec<Status>, PgPoolError> {
+        let client = driver::get(PG_CLUSTER.writer()).await?;
+        let exists: bool = client.query_one("SELECT to_regclass($1) IS NOT NULL", &[&self.quoted()]).await?.get(0);
+        match exists {
+            true => self.load_status(&client).await,
+            false => Ok(self.compare(&[])),
+        }
+    }
+
+    /// Reverts the latest applied migration with its down script.
+    /// Returns its version, or `None` when nothing is applied.
+    pub async fn down(&self) -> Result<Option<i64>, PgPoolError> {
+        self.locked(async |client| {
+            let latest = client.query_opt(
+                &format!("SELECT version FROM {} ORDER BY version DESC LIMIT 1", self.quoted()), &[]
+            ).await?;
+            let Some(version) = latest.map(|r| r.get::<_, i64>(0)) else { return Ok(None) };
+            let m = self.migrations.iter().find(|m| m.version == version)
+                .ok_or_else(|| PgPoolError::Migration(format!("migration {version} not found")))?;
+            let down = m.down.as_ref()
+                .ok_or_else(|| PgPoolError::Migration(format!("migration {version} {} has no down script", m.name)))?;
+            info!("Revert migration {} {}", m.version, m.name);
+            self.apply(client, m, down, false).await?;
+            Ok(Some(version))
+        }).await
+    }
+
+    /// Accepts the current files: records new checksums for modified
+    /// migrations and forgets missing ones. Returns the affected versions.
+    pub async fn repair(&self) -> Result<Vec<i64>, PgPoolError> {
+        self.locked(async |client| {
+            let mut repaired = Vec::new();
+            for s in self.load_status(client).await? {
+                match s.state {
+                    State::Modified => {
+                        let m = self.migrations.iter().find(|m| m.version == s.version).unwrap();
+                        client.execute(
+                            &format!("UPDATE {} SET name = $2, checksum = $3 WHERE version = $1", self.quoted()),
+                            &[&m.version, &m.name.as_ref(), &m.checksum()]
+                        ).await?;
+                    }
+                    State::Missing => {
+                        client.execute(&format!("DELETE FROM {} WHERE version = $1", self.quoted()), &[&s.version])
+                            .await?;
+                    }
+                    State::Applied | State::Pending => continue,
+                }
+                info!("Repair migration {} {}", s.version, s.name);
+                repaired.push(s.version);
+            }
+            Ok(repaired)
+        }).await
+    }
+
+    fn quoted(&self) -> String {
+        quote_qualified(&self.table)
+    }
+
+    async fn locked<T>(&self, f: impl AsyncFnOnce(&mut Client) -> Result<T, PgPoolError>) -> Result<T, PgPoolError> {
+        // Dropped mid-way the session is discarded, taking the lock with it
+        let mut pending = Pending::new(driver::get(PG_CLUSTER.writer()).await?);
+        let key = lock_key(&self.table);
+        pending.client().execute("SELECT pg_advisory_lock($1)", &[&key]).await?;
+        let result = async {
+            let client = pending.client_mut();
+            client.batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    version bigint PRIMARY KEY,
+                    name text NOT NULL,
+                    checksum text NOT NULL,
+                    applied_at timestamptz NOT NULL DEFAULT now()
+                )", self.quoted())).await?;
+            f(client).await
+        }.await;
+        let client = pending.done();
+        if let Err(e) = client.execute("SELECT pg_advisory_unlock($1)", &[&key]).await {
+            // The lock is released with the session
+            warn!("Cannot release migration lock {key}: {e}");
+            drop(Object::take(client));
+        }
+        result
+    }
+
+    async fn load_status(&self, client: &Client) -> Result<Vec<Status>, PgPoolError> {
+        let rows = client.query(&format!("SELECT version, name, checksum FROM {} ORDER BY version", self.quoted()), &[])
+            .await?;
+        Ok(self.compare(&rows))
+    }
+
+    // Applied rows against the migration files
+    fn compare(&self, rows: &[Row]) -> Vec<Status> {
+        let mut status = Vec::new();
+        for row in rows {
+            let version: i64 = row.get(0);
+            let state = match self.migrations.iter().find(|m| m.version == version) {
+                Some(m) if m.checksum() == row.get::<_, &str>(2) => State::Applied,
+                Some(_) => State::Modified,
+                None => State::Missing,
+            };
+            status.push(Status { version, name: row.get(1), state });
+        }
+        for m in &self.migrations {
+            if !status.iter().any(|s| s.version == m.version) {
+                status.push(Status { version: m.version, name: m.name.to_string(), state: State::Pending });
+            }
+        }
+        status.sort_by_key(|s| s.version);
+        status
+    }
+
+    async fn apply(&self, client: &mut Client, m: &Migration, sql: &str, up: bool) -> Result<(), PgPoolError> {
+        let tx = client.transaction().await?;
+        tx.batch_execute(sql).await?;
+        if up {
+            tx.execute(
+                &format!("INSERT INTO {} (version, name, checksum) VALUES ($1, $2, $3)", self.quoted()),
+                &[&m.version, &m.name.as_ref(), &m.checksum()]
+            ).await?;
+        } else {
+            tx.execute(&format!("DELETE FROM {} WHERE version = $1", self.quoted()), &[&m.version]).await?;
+        }
+        Ok(tx.commit().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_file_names() {
+        assert_eq!(parse_file_name("0002_add_users.sql"), Some((2, "add_users", false)));
+        assert_eq!(parse_file_name("2_add_users.down.sql"), Some((2, "add_users", true)));
+        assert_eq!(parse_file_name("README.md"), None);
+        assert_eq!(parse_file_name("init.sql"), None);
+    }
+
+    #[test]
+    fn it_finds_duplicate_versions() {
+        assert_eq!(duplicate_version([(1, false), (1, true), (2, false)]), None);
+        assert_eq!(duplicate_version([(1, false), (2, false), (1, false)]), Some(1));
+    }
+}
@@ -0,0 +1,18 @@
+//! Migration file names, shared with `embed_migrations!` in pg-pool-derive.
+
+/// Splits `<version>_<name>.sql` or `<version>_<name>.down.sql`.
+pub fn parse_file_name(file: &str) -> Option<(i64, &str, bool)> {
+    let stem = file.strip_suffix(".sql")?;
+    let (stem, down) = match stem.strip_suffix(".down") {
+        Some(stem) => (stem, true),
+        None => (stem, false),
+    };
+    let (version, name) = stem.split_once('_')?;
+    Some((version.parse().ok()?, name, down))
+}
+
+/// First version given twice for the same direction, as `(version, down)`.
+pub fn duplicate_version(files: impl IntoIterator<Item = (i64, bool)>) -> Option<i64> {
+    let mut seen = std::collections::HashSet::new();
+    files.into_iter().find(|file| !seen.insert(*file)).map(|(version, _)| version)
+}
@@ -0,0 +1,198 @@
+use deadpool_postgres::tokio_postgres::types::ToSql;
+use crate::PgPoolError;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, LazyLock, RwLock};
+
+/// Values for `:name` placeholders. Derive it with `#[derive(NamedParams)]`
+/// or pass a map of values.
+pub trait NamedParams {
+    fn param(&self, name: &str) -> Option<&(dyn ToSql + Sync)>;
+}
+
+macro_rules! impl_map {
+    ($map:ident, $($bound:path),+; $value:ty) => {
+        impl<K: Borrow<str> $(+ $bound)+> NamedParams for $map<K, $value> {
+            fn param(&self, name: &str) -> Option<&(dyn ToSql + Sync)> {
+                self.get(name).map(|v| &**v as &(dyn ToSql + Sync))
+            }
+        }
+    };
+}
+
+impl_map!(HashMap, Eq, Hash; &(dyn ToSql + Sync));
+impl_map!(HashMap, Eq, Hash; Box<dyn ToSql + Send + Sync>);
+impl_map!(BTreeMap, Ord; &(dyn ToSql + Sync));
+impl_map!(BTreeMap, Ord; Box<dyn ToSql + Send + Sync>);
+
+impl NamedParams for [(&str, &(dyn ToSql + Sync))] {
+    fn param(&self, name: &str) -> Option<&(dyn ToSql + Sync)> {
+        self.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+    }
+}
+
+impl<const N: usize> NamedParams for [(&str, &(dyn ToSql + Sync)); N] {
+    fn param(&self, name: &str) -> Option<&(dyn ToSql + Sync)> {
+        self.as_slice().param(name)
+    }
+}
+
+/// Query with `:name` placeholders rewritten to `$n`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Named {
+    pub sql: String,
+    pub names: Vec<String>,   // names[n - 1] is bound to $n
+}
+
+impl Named {
+    pub fn bind<'a, P>(&self, params: &'a P) -> Result<Vec<&'a (dyn ToSql + Sync)>, PgPoolError>
+    where
+        P: ?Sized + NamedParams,
+    {
+        self.names.iter()
+            .map(|name| params.param(name)
+                .ok_or_else(|| PgPoolError::Param(format!("missing value for :{name}"))))
+            .collect()
+    }
+}
+
+// Rewrites keyed by the original query, shared by all connections.
+// Each rewritten query is then prepared through `prepare_typed_cached`.
+static REWRITES: LazyLock<RwLock<HashMap<String, Arc<Named>>>> = LazyLock::new(Default::default);
+
+pub(crate) fn rewrite_cached(query: &str) -> Result<Arc<Named>, PgPoolError> {
+    if let Some(named) = REWRITES.read().unwrap().get(query) {
+        return Ok(named.clone());
+    }
+    let named = Arc::new(rewrite(query)?);
+    REWRITES.write().unwrap().insert(query.to_string(), named.clone());
+    Ok(named)
+}
+
+/// Replaces `:name` with `$n`, numbering each distinct name once. Literals,
+/// quoted identifiers, comments, dollar quoted bodies and `::` casts are left alone.
+fn rewrite(query: &str) -> Result<Named, PgPoolError> {
+    let bytes = query.as_bytes();
+    let mut sql = String::with_capacity(query.len());
+    let mut names: Vec<String> = Vec::new();
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' => {
+                let escapes = i > 0 && matches!(bytes[i - 1], b'e' | b'E')
+                    && (i < 2 || !is_ident(bytes[i - 2]));
+                i = skip_quoted(bytes, i, b'\'', escapes);
+            }
+            b'"' => i = skip_quoted(bytes, i, b'"', false),
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                i = query[i..].find('\n').map_or(bytes.len(), |n| i + n + 1);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => i = skip_block_comment(bytes, i),
+            b'$' => {
+                if bytes.get(i + 1).is_some_and(u8::is_ascii_digit) {
+                    return Err(PgPoolError::Param("positional $n parameters cannot be mixed with :name".into()));
+                }
+                i = skip_dollar_quoted(query, i);
+            }
+            b':' if bytes.get(i + 1) == Some(&b':') => i += 2,
+            b':' if bytes.get(i + 1).is_some_and(|&b| b == b'_' || b.is_ascii_alphabetic()) => {
+                let end = (i + 1..bytes.len()).find(|&j| !is_ident(bytes[j])).unwrap_or(bytes.len());
+                let name = &query[i + 1..end];
+                let n = match names.iter().position(|n| n == name) {
+                    Some(n) => n + 1,
+                    None => {
+                        names.push(name.to_string());
+                        names.len()
+                    }
+                };
+                sql.push_str(&query[copied..i]);
+                sql.push_str(&format!("${n}"));
+                copied = end;
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    sql.push_str(&query[copied..]);
+    Ok(Named { sql, names })
+}
+
+fn is_ident(b: u8) -> bool {
+    b == b'_' || b.is_ascii_alphanumeric()
+}
+
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8, escapes: bool) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if escapes && bytes[i] == b'\\' {
+            i += 2;
+        } else if bytes[i] == quote {
+            if bytes.get(i + 1) != Some(&quote) {
+                return i + 1;
+            }
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    bytes.len()
+}
+
+fn skip_block_comment(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        match (bytes[i], bytes[i + 1]) {
+            (b'/', b'*') => { depth += 1; i += 2; }
+            (b'*', b'/') => {
+                depth -= 1;
+                i += 2;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+fn skip_dollar_quoted(query: &str, start: usize) -> usize {
+    let bytes = query.as_bytes();
+    let tag_end = (start + 1..bytes.len()).find(|&j| !is_ident(bytes[j]));
+    match tag_end {
+        Some(end) if bytes[end] == b'$' => {
+            let tag = &query[start..=end];
+            query[end + 1..].find(tag).map_or(bytes.len(), |n| end + 1 + n + tag.len())
+        }
+        _ => start + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_numbers_each_name_once() {
+        let named = rewrite("SELECT * FROM t WHERE a = :id OR b = :name OR c = :id").unwrap();
+        assert_eq!(named.sql, "SELECT * FROM t WHERE a = $1 OR b = $2 OR c = $1");
+        assert_eq!(named.names, ["id", "name"]);
+    }
+
+    #[test]
+    fn it_skips_literals_comments_and_casts() {
+        let query = "SELECT ':a', \":b\", E'\\':c', $$:d$$, $x$ :e $x$, :f::text -- :g\n/* :h /* :i */ */ FROM t";
+        let named = rewrite(query).unwrap();
+        assert_eq!(named.names, ["f"]);
+        assert_eq!(named.sql, query.replace(":f::", "$1::"));
+    }
+
+    #[test]
+    fn it_rejects_positional_params() {
+        assert!(matches!(rewrite("SELECT $1, :a"), Err(PgPoolError::Param(_))));
+    }
+}
@@ -2,90 +2,266 @@ pub use deadpool_postgres::{
     Client, PoolError
 };
 use deadpool_postgres::tokio_postgres::{
-    Error, Statement, ToStatement,
+    Statement, ToStatement,
     types::ToSql
 };
-use crate::{PG_POOL, Row, Type, driver::{self, PgPool}};
+use crate::{AdvisoryLock, FromRow, Leader, NamedParams, PG_CLUSTER, PgPoolError, QueryText, CopyOptions, CopyRow, QueryStream, Row, Transaction, TxOptions, Type};
+use futures_util::Stream;
+use std::time::Duration;
 
-pub async fn prepare(query: &str) -> Result<Statement, Error> {
-    driver::prepare(PgPool::Writer, query).await
+pub async fn prepare(query: &str) -> Result<Statement, PgPoolError> {
+    PG_CLUSTER.writer().prepare(query).await
 }
 
 pub async fn query<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Vec<Row>, Error>
+) -> Result<Vec<Row>, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    driver::query(PgPool::Writer, statement, params).await
+    PG_CLUSTER.writer().query(statement, params).await
 }
 
 pub async fn query_pp(
     query: &str,
     types: &[Type],
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Vec<Row>, Box<dyn std::error::Error + Send + Sync + 'static>>
+) -> Result<Vec<Row>, PgPoolError>
 {
-    driver::query_pp(PgPool::Writer, query, types, params).await
+    PG_CLUSTER.writer().query_pp(query, types, params).await
 }
 
 pub async fn query_one<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Row, Error>
+) -> Result<Row, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    driver::query_one(PgPool::Writer, statement, params).await
+    PG_CLUSTER.writer().query_one(statement, params).await
 }
 
 pub async fn query_one_pp(
     query: &str,
     types: &[Type],
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Row, Box<dyn std::error::Error + Send + Sync + 'static>>
+) -> Result<Row, PgPoolError>
 {
-    driver::query_one_pp(PgPool::Writer, query, types, params).await
+    PG_CLUSTER.writer().query_one_pp(query, types, params).await
 }
 
 pub async fn query_opt<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Option<Row>, Error>
+) -> Result<Option<Row>, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    driver::query_opt(PgPool::Writer, statement, params).await
+    PG_CLUSTER.writer().query_opt(statement, params).await
+}
+
+pub async fn query_opt_pp(
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Option<Row>, PgPoolError>
+{
+    PG_CLUSTER.writer().query_opt_pp(query, types, params).await
+}
+
+/// `query` failing with `PgPoolError::QueryTimeout` after `timeout`, overriding the config.
+pub async fn query_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<Vec<Row>, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.writer().query_timeout(statement, params, timeout).await
+}
+
+pub async fn query_one_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<Row, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.writer().query_one_timeout(statement, params, timeout).await
+}
+
+pub async fn query_opt_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<Option<Row>, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.writer().query_opt_timeout(statement, params, timeout).await
+}
+
+pub async fn execute_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<u64, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.writer().execute_timeout(statement, params, timeout).await
+}
+
+/// Query with `:name` placeholders, e.g. `WHERE id = :id`, bound from `params`.
+/// The rewrite to `$n` is cached and the statement goes through `prepare_typed_cached`.
+pub async fn query_named<P>(query: &str, params: &P) -> Result<Vec<Row>, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.writer().query_named(query, params).await
+}
+
+pub async fn query_one_named<P>(query: &str, params: &P) -> Result<Row, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.writer().query_one_named(query, params).await
+}
+
+pub async fn query_opt_named<P>(query: &str, params: &P) -> Result<Option<Row>, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.writer().query_opt_named(query, params).await
+}
+
+/// `query` mapping each row with `FromRow`.
+pub async fn query_as<R: FromRow>(
+    statement: &(impl ?Sized + ToStatement + QueryText),
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Vec<R>, PgPoolError>
+{
+    PG_CLUSTER.writer().query_as(statement, params).await
+}
+
+pub async fn query_one_as<R: FromRow>(
+    statement: &(impl ?Sized + ToStatement + QueryText),
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<R, PgPoolError>
+{
+    PG_CLUSTER.writer().query_one_as(statement, params).await
+}
+
+pub async fn query_opt_as<R: FromRow>(
+    statement: &(impl ?Sized + ToStatement + QueryText),
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Option<R>, PgPoolError>
+{
+    PG_CLUSTER.writer().query_opt_as(statement, params).await
+}
+
+/// Streams rows through a server side cursor, fetching `fetch_size` rows per round trip.
+/// The pooled client is held until the stream ends or is dropped.
+pub async fn query_stream(
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)],
+    fetch_size: u32
+) -> Result<QueryStream, PgPoolError>
+{
+    PG_CLUSTER.writer().query_stream(query, types, params, fetch_size).await
 }
 
 pub async fn execute<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<u64, Error>
+) -> Result<u64, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.writer().execute(statement, params).await
+}
+
+pub async fn execute_pp(
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<u64, PgPoolError>
 {
-    driver::execute(PgPool::Writer, statement, params).await
+    PG_CLUSTER.writer().execute_pp(query, types, params).await
+}
+
+pub async fn execute_named<P>(query: &str, params: &P) -> Result<u64, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.writer().execute_named(query, params).await
+}
+
+pub async fn transaction<T, F>(opts: TxOptions, f: F) -> Result<T, PgPoolError>
+where
+    F: AsyncFnMut(&mut Transaction<'_>) -> Result<T, PgPoolError>,
+{
+    PG_CLUSTER.writer().transaction(opts, f).await
+}
+
+/// Bulk loads `rows` with binary `COPY ... FROM STDIN`. Returns the number of rows copied.
+pub async fn copy_in<S, R>(table: &str, columns: &[&str], rows: S) -> Result<u64, PgPoolError>
+where
+    S: Stream<Item = R>,
+    R: CopyRow,
+{
+    PG_CLUSTER.writer().copy_in(table, columns, rows).await
+}
+
+pub async fn copy_in_with<S, R>(
+    table: &str,
+    columns: &[&str],
+    rows: S,
+    opts: CopyOptions
+) -> Result<u64, PgPoolError>
+where
+    S: Stream<Item = R>,
+    R: CopyRow,
+{
+    PG_CLUSTER.writer().copy_in_with(table, columns, rows, opts).await
+}
+
+/// Waits for the session advisory lock `key`. See `lock_key` for named locks.
+pub async fn advisory_lock(key: i64) -> Result<AdvisoryLock, PgPoolError> {
+    PG_CLUSTER.writer().advisory_lock(key).await
+}
+
+/// `None` when another session holds `key`.
+pub async fn try_advisory_lock(key: i64) -> Result<Option<AdvisoryLock>, PgPoolError> {
+    PG_CLUSTER.writer().try_advisory_lock(key).await
+}
+
+/// Starts competing for leadership on `key`, checking every `interval`.
+/// Uses its own connection to `db` rather than a pooled one.
+pub fn leader(key: i64, interval: Duration) -> Result<Leader, PgPoolError> {
+    PG_CLUSTER.leader(key, interval)
 }
 
 pub async fn prepare_typed_cached(
     query: &str,
     types: &[Type],
-) -> Result<Statement, Box<dyn std::error::Error>> {
-    get().await?
-        .prepare_typed_cached(query, types).await
-        .map_err(|e| e.into() )
+) -> Result<Statement, PgPoolError> {
+    PG_CLUSTER.writer().prepare_typed_cached(query, types).await
 }
 
-pub async fn get() -> Result<Client, PoolError> {
-    PG_POOL.get().await
+pub async fn get() -> Result<Client, PgPoolError> {
+    PG_CLUSTER.writer().get().await
 }
 
 pub fn close() {
-    driver::close(&PG_POOL);
+    PG_CLUSTER.writer().close()
 }
 
+#[deprecated(note = "bind the values as an array with `= ANY($1)`, or use `pg_pool::quote_literals`")]
 pub fn vec2string<T: std::fmt::Display>(v: &Box<[T]>) -> String {
-    v.iter().map(|s| format!("'{}'", s)).collect::<Vec<String>>().join(",")
+    crate::quote_literals(v)
 }
@@ -2,93 +2,236 @@ pub use deadpool_postgres::{
     Client, PoolError
 };
 use deadpool_postgres::tokio_postgres::{
-    Error, Statement, ToStatement,
+    Statement, ToStatement,
     types::ToSql
 };
-use std::sync::LazyLock;
-use crate::{PGR_POOL, Row, Type, driver::{self, PgPool}};
+use crate::{FromRow, NamedParams, PG_CLUSTER, PgPoolError, QueryText, BinaryCopyOutRow, CopyOptions, CopyOut, QueryStream, Row, Transaction, TxOptions, Type};
+use bytes::Bytes;
+use std::time::Duration;
 
-pub async fn prepare(query: &str) -> Result<Statement, Error> {
-    driver::prepare(PgPool::Reader, query).await
+pub async fn prepare(query: &str) -> Result<Statement, PgPoolError> {
+    PG_CLUSTER.reader().prepare(query).await
 }
 
 pub async fn query<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Vec<Row>, Error>
+) -> Result<Vec<Row>, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    driver::query(PgPool::Reader, statement, params).await
+    PG_CLUSTER.reader().query(statement, params).await
 }
 
 pub async fn query_pp(
     query: &str,
     types: &[Type],
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Vec<Row>, Box<dyn std::error::Error + Send + Sync + 'static>>
+) -> Result<Vec<Row>, PgPoolError>
 {
-    driver::query_pp(PgPool::Reader, query, types, params).await
+    PG_CLUSTER.reader().query_pp(query, types, params).await
 }
 
 pub async fn query_one<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Row, Error>
+) -> Result<Row, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    driver::query_one(PgPool::Reader, statement, params).await
+    PG_CLUSTER.reader().query_one(statement, params).await
 }
 
 pub async fn query_one_pp(
     query: &str,
     types: &[Type],
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Row, Box<dyn std::error::Error + Send + Sync + 'static>>
+) -> Result<Row, PgPoolError>
 {
-    driver::query_one_pp(PgPool::Reader, query, types, params).await
+    PG_CLUSTER.reader().query_one_pp(query, types, params).await
 }
 
 pub async fn query_opt<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<Option<Row>, Error>
+) -> Result<Option<Row>, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    T: ?Sized + ToStatement + QueryText,
 {
-    driver::query_opt(PgPool::Reader, statement, params).await
+    PG_CLUSTER.reader().query_opt(statement, params).await
+}
+
+pub async fn query_opt_pp(
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Option<Row>, PgPoolError>
+{
+    PG_CLUSTER.reader().query_opt_pp(query, types, params).await
+}
+
+/// `query` failing with `PgPoolError::QueryTimeout` after `timeout`, overriding the config.
+pub async fn query_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<Vec<Row>, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.reader().query_timeout(statement, params, timeout).await
+}
+
+pub async fn query_one_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<Row, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.reader().query_one_timeout(statement, params, timeout).await
+}
+
+pub async fn query_opt_timeout<T>(
+    statement: &T,
+    params: &[&(dyn ToSql + Sync)],
+    timeout: Duration
+) -> Result<Option<Row>, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.reader().query_opt_timeout(statement, params, timeout).await
+}
+
+/// Query with `:name` placeholders, e.g. `WHERE id = :id`, bound from `params`.
+/// The rewrite to `$n` is cached and the statement goes through `prepare_typed_cached`.
+pub async fn query_named<P>(query: &str, params: &P) -> Result<Vec<Row>, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.reader().query_named(query, params).await
+}
+
+pub async fn query_one_named<P>(query: &str, params: &P) -> Result<Row, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.reader().query_one_named(query, params).await
+}
+
+pub async fn query_opt_named<P>(query: &str, params: &P) -> Result<Option<Row>, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.reader().query_opt_named(query, params).await
+}
+
+/// `query` mapping each row with `FromRow`.
+pub async fn query_as<R: FromRow>(
+    statement: &(impl ?Sized + ToStatement + QueryText),
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Vec<R>, PgPoolError>
+{
+    PG_CLUSTER.reader().query_as(statement, params).await
+}
+
+pub async fn query_one_as<R: FromRow>(
+    statement: &(impl ?Sized + ToStatement + QueryText),
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<R, PgPoolError>
+{
+    PG_CLUSTER.reader().query_one_as(statement, params).await
+}
+
+pub async fn query_opt_as<R: FromRow>(
+    statement: &(impl ?Sized + ToStatement + QueryText),
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<Option<R>, PgPoolError>
+{
+    PG_CLUSTER.reader().query_opt_as(statement, params).await
+}
+
+/// Streams rows through a server side cursor, fetching `fetch_size` rows per round trip.
+/// The pooled client is held until the stream ends or is dropped.
+pub async fn query_stream(
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)],
+    fetch_size: u32
+) -> Result<QueryStream, PgPoolError>
+{
+    PG_CLUSTER.reader().query_stream(query, types, params, fetch_size).await
 }
 
 pub async fn execute<T>(
     statement: &T,
     params: &[&(dyn ToSql + Sync)]
-) -> Result<u64, Error>
+) -> Result<u64, PgPoolError>
+where
+    T: ?Sized + ToStatement + QueryText,
+{
+    PG_CLUSTER.reader().execute(statement, params).await
+}
+
+pub async fn execute_pp(
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<u64, PgPoolError>
+{
+    PG_CLUSTER.reader().execute_pp(query, types, params).await
+}
+
+pub async fn execute_named<P>(query: &str, params: &P) -> Result<u64, PgPoolError>
+where
+    P: ?Sized + NamedParams,
+{
+    PG_CLUSTER.reader().execute_named(query, params).await
+}
+
+/// Read-only transaction. `opts.read_only` is always set.
+pub async fn transaction<T, F>(opts: TxOptions, f: F) -> Result<T, PgPoolError>
 where
-    T: ?Sized + ToStatement,
+    F: AsyncFnMut(&mut Transaction<'_>) -> Result<T, PgPoolError>,
 {
-    driver::execute(PgPool::Reader, statement, params).await
+    PG_CLUSTER.reader().transaction(opts, f).await
+}
+
+/// Exports `query` with binary `COPY ... TO STDOUT`, decoding rows by the query's column types.
+pub async fn copy_out(query: &str) -> Result<CopyOut<BinaryCopyOutRow>, PgPoolError> {
+    PG_CLUSTER.reader().copy_out(query).await
+}
+
+pub async fn copy_out_with(query: &str, opts: CopyOptions) -> Result<CopyOut<BinaryCopyOutRow>, PgPoolError> {
+    PG_CLUSTER.reader().copy_out_with(query, opts).await
+}
+
+/// Exports `query` as raw CSV bytes with a header line.
+pub async fn copy_out_csv(query: &str) -> Result<CopyOut<Bytes>, PgPoolError> {
+    PG_CLUSTER.reader().copy_out_csv(query).await
+}
+
+pub async fn copy_out_csv_with(query: &str, opts: CopyOptions) -> Result<CopyOut<Bytes>, PgPoolError> {
+    PG_CLUSTER.reader().copy_out_csv_with(query, opts).await
 }
 
 pub async fn prepare_typed_cached(
     query: &str,
     types: &[Type],
-) -> Result<Statement, Box<dyn std::error::Error>> {
-    get().await?
-        .prepare_typed_cached(query, types).await
-        .map_err(|e| e.into() )
+) -> Result<Statement, PgPoolError> {
+    PG_CLUSTER.reader().prepare_typed_cached(query, types).await
 }
 
-pub async fn get() -> Result<Client, PoolError> {
-    driver::get(PgPool::Reader).await
+pub async fn get() -> Result<Client, PgPoolError> {
+    PG_CLUSTER.reader().get().await
 }
 
 pub fn close() {
-    if let Some(pool) = LazyLock::force(&PGR_POOL) {
-        driver::close(pool);
-    }
+    PG_CLUSTER.reader().close()
 }
 
+#[deprecated(note = "bind the values as an array with `= ANY($1)`, or use `pg_pool::quote_literals`")]
 pub fn vec2string<T: std::fmt::Display>(v: &Box<[T]>) -> String {
-    v.iter().map(|s| format!("'{}'", s)).collect::<Vec<String>>().join(",")
+    crate::quote_literals(v)
 }
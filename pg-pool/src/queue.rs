@@ -0,0 +1,249 @@
+//! Durable job queue in the `pg_pool_jobs` table.
+//!
+//! Jobs are enqueued through any `PgExecutor`, so enqueueing inside a
+//! transaction only publishes the job on commit. Workers claim jobs with
+//! `FOR UPDATE SKIP LOCKED` and hold a lease on them; a job whose lease
+//! expires, e.g. because its worker died, is claimed again while it has
+//! attempts left.
+//! Failed jobs are retried with exponential backoff, then marked `dead`.
+use deadpool_postgres::tokio_postgres::types::Type;
+use futures_util::{StreamExt, future::{self, Either}, pin_mut};
+use crate::{PG_CLUSTER, PgExecutor, PgPoolError, Row, driver, listen, lock::{self, lock_key}};
+use log::{debug, warn};
+use std::fmt::Display;
+use std::time::{Duration, SystemTime};
+
+const TABLE: &str = "pg_pool_jobs";
+const CHANNEL: &str = "pg_pool_jobs";
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: i64,
+    pub queue: String,
+    pub payload: String,
+    pub attempts: i32,       // Including the current one
+    pub max_attempts: i32,
+    pub last_error: Option<String>,
+}
+
+impl Job {
+    fn from_row(row: &Row) -> Self {
+        Self {
+            id: row.get("id"),
+            queue: row.get("queue"),
+            payload: row.get("payload"),
+            attempts: row.get("attempts"),
+            max_attempts: row.get("max_attempts"),
+            last_error: row.get("last_error"),
+        }
+    }
+}
+
+/// Creates the jobs table if it does not exist.
+pub async fn install() -> Result<(), PgPoolError> {
+    let lock = lock::advisory_lock(PG_CLUSTER.writer(), lock_key(TABLE)).await?;
+    lock.client().batch_execute(&format!("
+        CREATE TABLE IF NOT EXISTS {TABLE} (
+            id bigserial PRIMARY KEY,
+            queue text NOT NULL,
+            payload text NOT NULL,
+            status text NOT NULL DEFAULT 'pending',
+            attempts int NOT NULL DEFAULT 0,
+            max_attempts int NOT NULL,
+            run_at timestamptz NOT NULL DEFAULT now(),
+            locked_until timestamptz,
+            last_error text,
+            created_at timestamptz NOT NULL DEFAULT now()
+        );
+        CREATE INDEX IF NOT EXISTS {TABLE}_claim ON {TABLE} (queue, run_at) WHERE status <> 'dead';
+    ")).await?;
+    lock.unlock().await
+}
+
+#[derive(Debug, Clone)]
+pub struct Queue {
+    name: String,
+    max_attempts: i32,
+    backoff: Duration,
+    max_backoff: Duration,
+    lease: Duration,
+    poll_interval: Duration,
+}
+
+impl Queue {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            max_attempts: 5,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(3600),
+            lease: Duration::from_secs(300),
+            poll_interval: Duration::from_secs(5),
+        }
+    }
+
+    /// Attempts before a job is marked dead. Default 5.
+    pub fn max_attempts(mut self, n: i32) -> Self {
+        self.max_attempts = n.max(1);
+        self
+    }
+
+    /// First retry delay, doubled per attempt up to `max`. Default 1s up to 1h.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.backoff = base;
+        self.max_backoff = max;
+        self
+    }
+
+    /// How long a claimed job is reserved for its worker. Default 5min.
+    pub fn lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Polling fallback when no notification arrives. Default 5s.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub async fn enqueue<E: PgExecutor>(&self, executor: &E, payload: &str) -> Result<i64, PgPoolError> {
+        self.enqueue_at(executor, payload, SystemTime::now()).await
+    }
+
+    /// Enqueues a job that runs no earlier than `run_at`.
+    pub async fn enqueue_at<E: PgExecutor>(
+        &self,
+        executor: &E,
+        payload: &str,
+        run_at: SystemTime
+    ) -> Result<i64, PgPoolError> {
+        let row = executor.query_one_pp(
+            &format!("WITH job AS (
+                INSERT INTO {TABLE} (queue, payload, max_attempts, run_at) VALUES ($1, $2, $3, $4) RETURNING id
+            ) SELECT id, pg_notify('{CHANNEL}', $1) FROM job"),
+            &[Type::TEXT, Type::TEXT, Type::INT4, Type::TIMESTAMPTZ],
+            &[&self.name, &payload, &self.max_attempts, &run_at]
+        ).await?;
+        Ok(row.get(0))
+    }
+
+    /// Claims and handles one due job. Returns false when none was due.
+    pub async fn run_once<F, Fut, E>(&self, handler: &mut F) -> Result<bool, PgPoolError>
+    where
+        F: FnMut(Job) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let Some(job) = self.claim().await? else { return Ok(false) };
+        match handler(job.clone()).await {
+            Ok(()) => {
+                // Another worker owns the job once the lease expired
+                let n = driver::execute_pp(PG_CLUSTER.writer(),
+                    &format!("DELETE FROM {TABLE} WHERE id = $1 AND attempts = $2"),
+                    &[Type::INT8, Type::INT4], &[&job.id, &job.attempts]).await?;
+                if n == 0 {
+                    warn!("Job {} in {} lost its lease during attempt {}", job.id, job.queue, job.attempts);
+                }
+            }
+            Err(e) => self.fail(&job, &e.to_string()).await?,
+        }
+        Ok(true)
+    }
+
+    /// Handles jobs until the future is dropped. Waits for a notification or
+    /// `poll_interval` when the queue is empty.
+    pub async fn run<F, Fut, E>(&self, mut handler: F)
+    where
+        F: FnMut(Job) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: Display,
+    {
+        let mut listener = match listen(&[CHANNEL]).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                warn!("Job queue {} polls without notifications: {e}", self.name);
+                None
+            }
+        };
+        loop {
+            match self.run_once(&mut handler).await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => warn!("Job queue {} failed: {e}", self.name),
+            }
+            let sleep = tokio::time::sleep(self.poll_interval);
+            pin_mut!(sleep);
+            if let Some(l) = listener.as_mut() {
+                let next = l.next();
+                pin_mut!(next);
+                if let Either::Left((None, _)) = future::select(next, sleep).await {
+                    listener = None;
+                }
+            } else {
+                sleep.await;
+            }
+        }
+    }
+
+    /// Jobs that used up their attempts.
+    pub async fn dead(&self) -> Result<Vec<Job>, PgPoolError> {
+        let rows = driver::query_pp(PG_CLUSTER.writer(),
+            &format!("SELECT * FROM {TABLE} WHERE queue = $1 AND status = 'dead' ORDER BY id"),
+            &[Type::TEXT], &[&self.name]).await?;
+        Ok(rows.iter().map(Job::from_row).collect())
+    }
+
+    /// Puts a dead job back in the queue with fresh attempts.
+    pub async fn retry_dead(&self, id: i64) -> Result<bool, PgPoolError> {
+        let n = driver::execute_pp(PG_CLUSTER.writer(),
+            &format!("UPDATE {TABLE} SET status = 'pending', attempts = 0, run_at = now()
+                WHERE id = $1 AND queue = $2 AND status = 'dead'"),
+            &[Type::INT8, Type::TEXT], &[&id, &self.name]).await?;
+        Ok(n == 1)
+    }
+
+    // Expired jobs without attempts left, e.g. whose worker kept crashing, are marked dead
+    async fn claim(&self) -> Result<Option<Job>, PgPoolError> {
+        let row = driver::query_opt_pp(PG_CLUSTER.writer(),
+            &format!("WITH expired AS (
+                    UPDATE {TABLE} SET status = 'dead', locked_until = NULL, last_error = 'lease expired'
+                    WHERE queue = $1 AND status = 'running' AND locked_until < now() AND attempts >= max_attempts
+                )
+                UPDATE {TABLE} SET status = 'running', attempts = attempts + 1,
+                    locked_until = now() + make_interval(secs => $2)
+                WHERE id = (
+                    SELECT id FROM {TABLE}
+                    WHERE queue = $1 AND status <> 'dead' AND run_at <= now()
+                        AND (status = 'pending' OR (locked_until < now() AND attempts < max_attempts))
+                    ORDER BY run_at, id
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *"),
+            &[Type::TEXT, Type::FLOAT8], &[&self.name, &self.lease.as_secs_f64()]).await?;
+        Ok(row.map(|row| Job::from_row(&row)))
+    }
+
+    // Skipped if the lease expired and another worker claimed the job meanwhile
+    async fn fail(&self, job: &Job, error: &str) -> Result<(), PgPoolError> {
+        if job.attempts >= job.max_attempts {
+            warn!("Job {} in {} is dead after {} attempts: {error}", job.id, job.queue, job.attempts);
+            driver::execute_pp(PG_CLUSTER.writer(),
+                &format!("UPDATE {TABLE} SET status = 'dead', locked_until = NULL, last_error = $2
+                    WHERE id = $1 AND attempts = $3"),
+                &[Type::INT8, Type::TEXT, Type::INT4], &[&job.id, &error, &job.attempts]).await?;
+        } else {
+            let wait = self.backoff
+                .saturating_mul(2u32.saturating_pow(job.attempts as u32 - 1))
+                .min(self.max_backoff);
+            debug!("Job {} in {} failed, retry in {wait:?}: {error}", job.id, job.queue);
+            driver::execute_pp(PG_CLUSTER.writer(),
+                &format!("UPDATE {TABLE} SET status = 'pending', locked_until = NULL, last_error = $2,
+                    run_at = now() + make_interval(secs => $3) WHERE id = $1 AND attempts = $4"),
+                &[Type::INT8, Type::TEXT, Type::FLOAT8, Type::INT4],
+                &[&job.id, &error, &wait.as_secs_f64(), &job.attempts]).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt::Display;
+
+/// Quotes an identifier, doubling embedded `"`. Always quoted, so the
+/// name is matched case sensitively.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Quotes each part of a dot separated name, e.g. `schema.table`.
+pub fn quote_qualified(name: &str) -> String {
+    name.split('.').map(quote_ident).collect::<Vec<_>>().join(".")
+}
+
+/// Quotes a string literal like Postgres `quote_literal`: `'` is doubled and
+/// values containing `\` use the `E'...'` form with `\` doubled.
+/// Prefer binding parameters; this is for SQL that cannot take them, e.g. DDL.
+pub fn quote_literal(value: &str) -> String {
+    let quoted = value.replace('\'', "''");
+    if quoted.contains('\\') {
+        format!("E'{}'", quoted.replace('\\', "\\\\"))
+    } else {
+        format!("'{quoted}'")
+    }
+}
+
+/// Comma separated `quote_literal` of each value, for an `IN (...)` list.
+pub fn quote_literals<T: Display>(values: &[T]) -> String {
+    values.iter().map(|v| quote_literal(&v.to_string())).collect::<Vec<_>>().join(",")
+}
+
+/// Values as a text array parameter, for `col = ANY($1::text[])`.
+/// Slices of types that implement `ToSql` bind as arrays directly: `= ANY($1)` with `&ids[..]`.
+pub fn text_array<T: Display>(values: &[T]) -> Vec<String> {
+    values.iter().map(T::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_quotes_like_postgres() {
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+        assert_eq!(quote_qualified("public.User"), "\"public\".\"User\"");
+        assert_eq!(quote_literal("it's"), "'it''s'");
+        assert_eq!(quote_literal("a\\'b"), "E'a\\\\''b'");
+        assert_eq!(quote_literals(&["x", "'); DROP TABLE t; --"]), "'x','''); DROP TABLE t; --'");
+    }
+}
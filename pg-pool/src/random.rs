@@ -0,0 +1,8 @@
+//! Randomness without a `rand` dependency.
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Random `u64` from a fresh std hasher seed. Not for cryptographic use.
+pub(crate) fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
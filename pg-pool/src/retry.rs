@@ -0,0 +1,129 @@
+use deadpool_postgres::tokio_postgres::error::SqlState;
+use crate::{PgPoolError, random::random};
+use log::warn;
+use server_conf::{SV_CONF, DbConf};
+use std::time::Duration;
+
+const DEFAULT_ATTEMPTS: u32 = 3;
+const DEFAULT_BACKOFF: u64 = 100;
+const DEFAULT_MAX_BACKOFF: u64 = 5000;
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,    // Total attempts including the first call
+    pub backoff: Duration,    // Base wait, doubled on each retry with full jitter
+    pub max_backoff: Duration,
+    pub classifier: fn(&PgPoolError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_ATTEMPTS,
+            backoff: Duration::from_millis(DEFAULT_BACKOFF),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF),
+            classifier: is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn from_conf(db: &DbConf) -> Self {
+        Self {
+            max_attempts: db.retry_max.unwrap_or(DEFAULT_ATTEMPTS).max(1),
+            backoff: Duration::from_millis(db.retry_backoff.unwrap_or(DEFAULT_BACKOFF)),
+            max_backoff: Duration::from_millis(db.retry_max_backoff.unwrap_or(DEFAULT_MAX_BACKOFF)),
+            ..Default::default()
+        }
+    }
+
+    /// Policy for wrapping writes in `retry`, configured by `db`.
+    /// Reads retry on their own with the reader's config.
+    pub fn writer() -> Self {
+        Self::from_conf(&SV_CONF.db)
+    }
+
+    pub fn none() -> Self {
+        Self { max_attempts: 1, ..Default::default() }
+    }
+
+    pub(crate) fn wait(&self, retry: u32) -> Duration {
+        let ceil = self.backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let jitter = random() % (ceil.as_millis() as u64 + 1);
+        Duration::from_millis(jitter)
+    }
+}
+
+/// Runs `f` until it succeeds, fails with an error the policy does not
+/// classify as transient, or runs out of attempts.
+/// Only wrap idempotent operations. Do not wrap `pgr::*` or other reader
+/// calls: they already retry, so the attempts would multiply.
+pub async fn retry<T, F, Fut>(policy: &RetryPolicy, mut f: F) -> Result<T, PgPoolError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, PgPoolError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e) if attempt < policy.max_attempts && (policy.classifier)(&e) => {
+                let wait = policy.wait(attempt - 1);
+                warn!("Retry ({attempt}/{}) after {wait:?}: {e}", policy.max_attempts - 1);
+                tokio::time::sleep(wait).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Default classifier: connection loss, server shutdown or startup,
+/// too many connections, and replica recovery conflicts.
+pub fn is_transient(e: &PgPoolError) -> bool {
+    match e {
+        PgPoolError::Connection(_) => true,
+        PgPoolError::Sql(_) => matches!(e.code(),
+            Some(&SqlState::ADMIN_SHUTDOWN) |
+            Some(&SqlState::CRASH_SHUTDOWN) |
+            Some(&SqlState::CANNOT_CONNECT_NOW) |
+            Some(&SqlState::CONNECTION_EXCEPTION) |
+            Some(&SqlState::CONNECTION_FAILURE) |
+            Some(&SqlState::SQLCLIENT_UNABLE_TO_ESTABLISH_SQLCONNECTION) |
+            Some(&SqlState::TOO_MANY_CONNECTIONS) |
+            Some(&SqlState::T_R_SERIALIZATION_FAILURE)),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_caps_backoff() {
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(250),
+            ..Default::default()
+        };
+        for retry in 0..8 {
+            assert!(policy.wait(retry) <= Duration::from_millis(250));
+        }
+        assert!(policy.wait(0) <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn it_keeps_at_least_one_attempt() {
+        let db = DbConf { retry_max: Some(0), ..Default::default() };
+        assert_eq!(RetryPolicy::from_conf(&db).max_attempts, 1);
+    }
+
+    #[test]
+    fn it_reads_max_backoff() {
+        let db = DbConf { retry_max_backoff: Some(250), ..Default::default() };
+        assert_eq!(RetryPolicy::from_conf(&db).max_backoff, Duration::from_millis(250));
+        assert_eq!(RetryPolicy::from_conf(&DbConf::default()).max_backoff, Duration::from_secs(5));
+    }
+}
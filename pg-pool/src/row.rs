@@ -0,0 +1,48 @@
+use crate::{PgPoolError, Row};
+
+/// Builds a value from a result row. Derive it with `#[derive(FromRow)]`.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, PgPoolError>;
+}
+
+pub(crate) fn from_rows<T: FromRow>(rows: Vec<Row>) -> Result<Vec<T>, PgPoolError> {
+    rows.iter().map(T::from_row).collect()
+}
+
+// Used by the derive macros
+pub mod private {
+    pub use deadpool_postgres::tokio_postgres::types::ToSql;
+    use deadpool_postgres::tokio_postgres::types::FromSql;
+    use crate::{PgPoolError, Row};
+    use std::error::Error;
+
+    pub fn get<'a, T: FromSql<'a>>(
+        row: &'a Row,
+        field: &'static str,
+        column: &'static str
+    ) -> Result<T, PgPoolError> {
+        row.try_get(column)
+            .map_err(|e| PgPoolError::Column { field, column, source: e.into() })
+    }
+
+    /// `None` when the column is missing or NULL.
+    pub fn get_opt<'a, T: FromSql<'a>>(
+        row: &'a Row,
+        field: &'static str,
+        column: &'static str
+    ) -> Result<Option<T>, PgPoolError> {
+        if row.columns().iter().all(|c| c.name() != column) {
+            return Ok(None);
+        }
+        get::<Option<T>>(row, field, column)
+    }
+
+    pub fn convert<S, T>(value: S, field: &'static str, column: &'static str) -> Result<T, PgPoolError>
+    where
+        T: TryFrom<S>,
+        T::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        T::try_from(value)
+            .map_err(|e| PgPoolError::Column { field, column, source: e.into() })
+    }
+}
@@ -0,0 +1,385 @@
+//! Slow-query log, configured by `slow_query`, `slow_query_sample` and
+//! `slow_query_redact` in `db` / `dbr`.
+//!
+//! Parameter values are redacted unless `slow_query_redact` lists the
+//! columns to hide. Values are attributed to a column when they are compared
+//! with it (`col = $1`) or inserted into it; values that cannot be
+//! attributed stay redacted.
+use deadpool_postgres::tokio_postgres::{Statement, types::ToSql};
+use crate::{PgPoolError, Row, driver::PgPool, random::random};
+use log::warn;
+use server_conf::DbConf;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+const MAX_VALUE_LEN: usize = 64;
+
+/// SQL text of a statement argument, for logging. Prepared statements have none.
+pub trait QueryText {
+    fn query_text(&self) -> Option<&str>;
+}
+
+impl QueryText for str {
+    fn query_text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl QueryText for String {
+    fn query_text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl QueryText for Statement {
+    fn query_text(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub(crate) trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for Vec<Row> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl RowCount for Row {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl RowCount for Option<Row> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+impl RowCount for u64 {
+    fn row_count(&self) -> u64 {
+        *self
+    }
+}
+
+pub(crate) struct SlowLog {
+    threshold: Duration,
+    sample: f64,
+    redact: Option<Vec<String>>,   // None redacts every value
+}
+
+impl SlowLog {
+    pub(crate) fn from_conf(db: &DbConf) -> Option<Self> {
+        Some(Self {
+            threshold: Duration::from_millis(db.slow_query?),
+            sample: db.slow_query_sample.unwrap_or(1.0).clamp(0.0, 1.0),
+            redact: db.slow_query_redact.as_ref()
+                .map(|cols| cols.iter().map(|c| c.to_lowercase()).collect()),
+        })
+    }
+
+    fn sampled(&self) -> bool {
+        self.sample >= 1.0 ||
+            (random() as f64 / u64::MAX as f64) < self.sample
+    }
+
+    fn line(
+        &self,
+        pool: PgPool,
+        elapsed: Duration,
+        sql: Option<&str>,
+        rows: Option<u64>,
+        params: &[&(dyn ToSql + Sync)]
+    ) -> String {
+        let pool = pool.name();
+        let rows = rows.map_or("failed".to_string(), |n| format!("{n} rows"));
+        let columns = sql.map(param_columns).unwrap_or_default();
+        let params = params.iter().enumerate()
+            .map(|(i, value)| {
+                let shown = match (&self.redact, columns.get(&(i + 1))) {
+                    (Some(redact), Some(column)) => !redact.contains(column),
+                    _ => false,
+                };
+                match shown {
+                    true => format!("${} = {}", i + 1, truncate(format!("{value:?}"))),
+                    false => format!("${} = <redacted>", i + 1),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let sql = sql.map_or("<prepared statement>".to_string(), normalize);
+        format!("Slow query on {pool}: {elapsed:?}, {rows}: {sql} [{params}]")
+    }
+}
+
+/// Runs `query` and logs it to `log` when it takes longer than the threshold.
+pub(crate) async fn timed<R, F>(
+    log: Option<&SlowLog>,
+    pool: PgPool,
+    sql: Option<&str>,
+    params: &[&(dyn ToSql + Sync)],
+    query: F
+) -> Result<R, PgPoolError>
+where
+    R: RowCount,
+    F: Future<Output = Result<R, PgPoolError>>,
+{
+    let Some(log) = log else { return query.await };
+    let start = Instant::now();
+    let result = query.await;
+    let elapsed = start.elapsed();
+    if elapsed >= log.threshold && log.sampled() {
+        let rows = result.as_ref().ok().map(RowCount::row_count);
+        warn!("{}", log.line(pool, elapsed, sql, rows, params));
+    }
+    result
+}
+
+fn truncate(mut value: String) -> String {
+    if value.len() > MAX_VALUE_LEN {
+        let mut end = MAX_VALUE_LEN;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+        value.push('…');
+    }
+    value
+}
+
+/// Collapses whitespace, drops comments and replaces string literals with `?`.
+pub(crate) fn normalize(sql: &str) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                push(&mut out, &mut space, "?");
+            }
+            '-' if chars.next_if_eq(&'-').is_some() => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+                space = true;
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                while let Some(c) = chars.next() {
+                    if c == '*' && chars.next_if_eq(&'/').is_some() {
+                        break;
+                    }
+                }
+                space = true;
+            }
+            c if c.is_whitespace() => space = true,
+            c => push(&mut out, &mut space, c.encode_utf8(&mut [0; 4])),
+        }
+    }
+    out
+}
+
+fn push(out: &mut String, space: &mut bool, s: &str) {
+    if std::mem::take(space) && !out.is_empty() {
+        out.push(' ');
+    }
+    out.push_str(s);
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Param(usize),
+    Op(String),
+    Punct(char),
+    Literal,
+}
+
+fn tokenize(sql: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '\'' => {
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                tokens.push(Token::Literal);
+            }
+            '"' => {
+                let mut ident = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '"' && chars.next_if_eq(&'"').is_none() {
+                        break;
+                    }
+                    ident.push(c);
+                }
+                tokens.push(Token::Ident(ident.to_lowercase()));
+            }
+            '-' if chars.next_if_eq(&'-').is_some() => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                while let Some(c) = chars.next() {
+                    if c == '*' && chars.next_if_eq(&'/').is_some() {
+                        break;
+                    }
+                }
+            }
+            '$' if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let mut n = 0;
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    n = n * 10 + d.to_digit(10).unwrap() as usize;
+                }
+                tokens.push(Token::Param(n));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = c.to_lowercase().to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '$') {
+                    ident.extend(c.to_lowercase());
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            '<' | '>' | '=' | '!' | '~' => {
+                let mut op = c.to_string();
+                while let Some(c) = chars.next_if(|c| matches!(c, '<' | '>' | '=' | '!' | '~')) {
+                    op.push(c);
+                }
+                tokens.push(Token::Op(op));
+            }
+            c => tokens.push(Token::Punct(c)),
+        }
+    }
+    tokens
+}
+
+/// Column each `$n` is compared with or inserted into, where recognizable.
+fn param_columns(sql: &str) -> HashMap<usize, String> {
+    let tokens = tokenize(sql);
+    let mut columns = HashMap::new();
+
+    // col = $n, col LIKE $n, ...
+    for (i, token) in tokens.iter().enumerate() {
+        let Token::Param(n) = token else { continue };
+        let column = match tokens.get(i.wrapping_sub(2)..i) {
+            Some([Token::Ident(column), Token::Op(op)]) if op != "!" && op != "~" => column,
+            Some([Token::Ident(column), Token::Ident(kw)]) if kw == "like" || kw == "ilike" => column,
+            _ => continue,
+        };
+        columns.insert(*n, column.clone());
+    }
+
+    // INSERT INTO t (a, b) VALUES ($1, $2), ($3, $4)
+    let mut i = 0;
+    while let Some(start) = tokens[i..].iter().position(|t| *t == Token::Ident("insert".to_string())) {
+        i += start + 1;
+        let Some(values) = tokens[i..].iter().position(|t| *t == Token::Ident("values".to_string())) else { break };
+        let names = match tokens[i..i + values].iter().position(|t| *t == Token::Punct('(')) {
+            Some(open) => list(&tokens[i + open + 1..i + values]),
+            None => Vec::new(),
+        };
+        let mut j = i + values + 1;
+        while tokens.get(j) == Some(&Token::Punct('(')) {
+            let items = list(&tokens[j + 1..]);
+            for (name, item) in names.iter().zip(&items) {
+                if let ([Token::Ident(name)], [Token::Param(n), ..]) = (*name, *item) {
+                    columns.insert(*n, name.clone());
+                }
+            }
+            j += 1 + items.iter().map(|item| item.len() + 1).sum::<usize>();
+            if tokens.get(j) != Some(&Token::Punct(',')) {
+                break;
+            }
+            j += 1;
+        }
+        i = j;
+    }
+    columns
+}
+
+/// Splits tokens up to the closing parenthesis at commas.
+fn list(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct('(') => depth += 1,
+            Token::Punct(')') if depth == 0 => {
+                items.push(&tokens[start..i]);
+                break;
+            }
+            Token::Punct(')') => depth -= 1,
+            Token::Punct(',') if depth == 0 => {
+                items.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_normalizes_sql() {
+        assert_eq!(
+            normalize("SELECT *\n  FROM users -- all\n WHERE name = 'O''Brien' /* x */ AND id = $1"),
+            "SELECT * FROM users WHERE name = ? AND id = $1"
+        );
+    }
+
+    #[test]
+    fn it_finds_param_columns() {
+        let columns = param_columns("SELECT * FROM u WHERE u.email = $1 AND \"Name\" ILIKE $2 AND $3 < age");
+        assert_eq!(columns.get(&1).map(String::as_str), Some("email"));
+        assert_eq!(columns.get(&2).map(String::as_str), Some("name"));
+        assert_eq!(columns.get(&3), None);
+
+        let columns = param_columns("INSERT INTO u (email, password) VALUES ($1, $2::text), (now(), $3) RETURNING id");
+        assert_eq!(columns.get(&1).map(String::as_str), Some("email"));
+        assert_eq!(columns.get(&2).map(String::as_str), Some("password"));
+        assert_eq!(columns.get(&3).map(String::as_str), Some("password"));
+    }
+
+    #[test]
+    fn it_redacts_params() {
+        let log = SlowLog {
+            threshold: Duration::ZERO,
+            sample: 1.0,
+            redact: Some(vec!["password".to_string()]),
+        };
+        let sql = "UPDATE u SET password = $1 WHERE email = $2 AND $3";
+        let params: [&(dyn ToSql + Sync); 3] = [&"secret", &"a@example.com", &true];
+        assert_eq!(
+            log.line(PgPool::Writer, Duration::from_millis(5), Some(sql), Some(1), &params),
+            "Slow query on writer: 5ms, 1 rows: UPDATE u SET password = $1 WHERE email = $2 AND $3 \
+                [$1 = <redacted>, $2 = \"a@example.com\", $3 = <redacted>]"
+        );
+
+        let log = SlowLog { redact: None, ..log };
+        assert_eq!(
+            log.line(PgPool::Reader, Duration::from_millis(5), Some(sql), None, &params[1..2]),
+            "Slow query on reader: 5ms, failed: UPDATE u SET password = $1 WHERE email = $2 AND $3 [$1 = <redacted>]"
+        );
+    }
+}
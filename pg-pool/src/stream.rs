@@ -0,0 +1,157 @@
+use deadpool_postgres::{
+    Client, Object,
+    tokio_postgres::{Error, types::ToSql},
+};
+use futures_util::{Stream, stream};
+use crate::{
+    PgPoolError, Row, Type,
+    PgHandle,
+    driver::{self, invalid_statement},
+    retry::retry,
+};
+use log::debug;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+const CURSOR: &str = "pg_pool_stream";
+
+/// Rows fetched through a server side cursor, `fetch_size` rows at a time.
+/// Holds its pooled client until exhausted or dropped.
+pub struct QueryStream {
+    inner: Pin<Box<dyn Stream<Item = Result<Row, PgPoolError>> + Send>>,
+}
+
+impl Stream for QueryStream {
+    type Item = Result<Row, PgPoolError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+pub(crate) async fn query_stream(
+    db: PgHandle<'_>,
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)],
+    fetch_size: u32
+) -> Result<QueryStream, PgPoolError> {
+    let fetch_size = fetch_size.max(1);
+    let cursor = retry(&db.role().retry, || async move {
+        open(db, query, types, params, fetch_size).await
+    }).await?;
+
+    let inner = stream::unfold(cursor, |mut cursor| async move {
+        match cursor.next().await {
+            Ok(Some(row)) => Some((Ok(row), cursor)),
+            Ok(None) => None,
+            Err(e) => {
+                cursor.abort();
+                Some((Err(e.into()), cursor))
+            }
+        }
+    });
+    Ok(QueryStream { inner: Box::pin(inner) })
+}
+
+async fn open(
+    db: PgHandle<'_>,
+    query: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)],
+    fetch_size: u32
+) -> Result<Cursor, PgPoolError> {
+    let declare = format!("DECLARE {CURSOR} NO SCROLL CURSOR FOR {query}");
+    let mut client = driver::get(db).await?;
+    if let Err(err) = declare_cursor(&client, &declare, types, params).await {
+        if err.is_closed() {
+            debug!("Reconnect closed connection: {err}");
+            drop(Object::take(client));
+            client = driver::get(db).await?;
+        } else {
+            let _ = client.batch_execute("ROLLBACK").await;
+            if !invalid_statement(&err) {
+                return Err(err.into());
+            }
+            debug!("Re-prepare invalidated statement: {err}");
+            client.statement_cache.clear();
+        }
+        if let Err(err) = declare_cursor(&client, &declare, types, params).await {
+            let _ = client.batch_execute("ROLLBACK").await;
+            return Err(err.into());
+        }
+    }
+
+    Ok(Cursor {
+        client: Some(client),
+        rows: VecDeque::new(),
+        fetch: format!("FETCH FORWARD {fetch_size} FROM {CURSOR}"),
+        fetch_size: fetch_size as usize,
+        exhausted: false,
+    })
+}
+
+async fn declare_cursor(
+    client: &Client,
+    declare: &str,
+    types: &[Type],
+    params: &[&(dyn ToSql + Sync)]
+) -> Result<(), Error> {
+    client.batch_execute("BEGIN").await?;
+    let stmt = client.prepare_typed_cached(declare, types).await?;
+    client.execute(&stmt, params).await?;
+    Ok(())
+}
+
+struct Cursor {
+    client: Option<Client>,
+    rows: VecDeque<Row>,
+    fetch: String,
+    fetch_size: usize,
+    exhausted: bool,
+}
+
+impl Cursor {
+    async fn next(&mut self) -> Result<Option<Row>, Error> {
+        if let Some(row) = self.rows.pop_front() {
+            return Ok(Some(row));
+        }
+        let Some(client) = self.client.as_ref() else {
+            return Ok(None);
+        };
+        if !self.exhausted {
+            let rows = client.query(&self.fetch, &[]).await?;
+            self.exhausted = rows.len() < self.fetch_size;
+            self.rows = rows.into();
+            if let Some(row) = self.rows.pop_front() {
+                return Ok(Some(row));
+            }
+        }
+        client.batch_execute("COMMIT").await?;
+        self.client = None;
+        Ok(None)
+    }
+
+    /// Ends the open transaction in the background and returns the client to the pool.
+    /// Without a runtime the connection is discarded instead.
+    fn abort(&mut self) {
+        let Some(client) = self.client.take() else { return };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if client.batch_execute("ROLLBACK").await.is_err() {
+                        drop(Object::take(client));
+                    }
+                });
+            }
+            Err(_) => drop(Object::take(client)),
+        }
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
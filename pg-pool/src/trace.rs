@@ -0,0 +1,76 @@
+//! `tracing` spans, compiled in with the `tracing` feature.
+//! Without it `in_span!` just awaits the future and the span expression
+//! is not even compiled.
+use crate::driver::PgPool;
+
+/// Awaits `$fut` inside the span `$span`.
+#[cfg(feature = "tracing")]
+macro_rules! in_span {
+    ($span:expr, $fut:expr) => {
+        tracing::Instrument::instrument($fut, $span).await
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! in_span {
+    ($span:expr, $fut:expr) => {
+        $fut.await
+    };
+}
+pub(crate) use in_span;
+
+/// Waiting for a pooled connection of `pool`.
+#[cfg(feature = "tracing")]
+pub(crate) fn checkout(pool: PgPool) -> tracing::Span {
+    tracing::info_span!("pg_pool.checkout",
+        db.system = "postgresql",
+        pg_pool.pool = pool.name(),
+        pg_pool.served_by = tracing::field::Empty)
+}
+
+/// Records the pool that handed out the connection, which differs from the
+/// requested one when reads fall back to the writer.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn served_by(pool: PgPool) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("pg_pool.served_by", pool.name());
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn prepare(sql: &str) -> tracing::Span {
+    tracing::info_span!("pg_pool.prepare",
+        db.system = "postgresql",
+        db.statement = crate::slow_query::normalize(sql),
+        db.operation = operation(Some(sql)))
+}
+
+/// A statement on `pool`, including checkout, prepare and retries.
+#[cfg(feature = "tracing")]
+pub(crate) fn query(pool: PgPool, sql: Option<&str>) -> tracing::Span {
+    tracing::info_span!("pg_pool.query",
+        db.system = "postgresql",
+        db.statement = sql.map(crate::slow_query::normalize),
+        db.operation = operation(sql),
+        pg_pool.pool = pool.name())
+}
+
+/// Leading keyword, e.g. `SELECT`.
+#[cfg(any(feature = "tracing", test))]
+fn operation(sql: Option<&str>) -> Option<String> {
+    sql?.split(|c: char| !c.is_alphabetic())
+        .find(|word| !word.is_empty())
+        .map(str::to_uppercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_names_operations() {
+        assert_eq!(operation(Some("  select 1")).as_deref(), Some("SELECT"));
+        assert_eq!(operation(Some("(SELECT 1) UNION (SELECT 2)")).as_deref(), Some("SELECT"));
+        assert_eq!(operation(Some("")), None);
+        assert_eq!(operation(None), None);
+    }
+}
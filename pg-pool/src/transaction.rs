@@ -0,0 +1,148 @@
+use deadpool_postgres::{
+    Client,
+    Transaction as PoolTransaction,
+    tokio_postgres::{IsolationLevel, error::SqlState},
+};
+use crate::{PgHandle, PgPoolError, driver};
+use log::{debug, error};
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct TxOptions {
+    pub isolation_level: Option<IsolationLevel>,
+    pub read_only: bool,
+    pub deferrable: bool,
+    pub retries: u32,       // Max reruns on serialization failure or deadlock
+    pub backoff: Duration,  // Wait before the first rerun, doubled on each rerun
+}
+
+impl Default for TxOptions {
+    fn default() -> Self {
+        Self {
+            isolation_level: None,
+            read_only: false,
+            deferrable: false,
+            retries: 0,
+            backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Transaction handle passed to `pg::transaction` / `pgr::transaction` closures.
+/// Query methods of the underlying transaction are available through `Deref`.
+pub struct Transaction<'a> {
+    tx: PoolTransaction<'a>,
+}
+
+impl<'a> Transaction<'a> {
+    fn new(tx: PoolTransaction<'a>) -> Self {
+        Self { tx }
+    }
+
+    pub async fn commit(self) -> Result<(), PgPoolError> {
+        Ok(self.tx.commit().await?)
+    }
+
+    pub async fn rollback(self) -> Result<(), PgPoolError> {
+        Ok(self.tx.rollback().await?)
+    }
+
+    /// Opens a `SAVEPOINT`. `commit` releases it, and `rollback` or dropping
+    /// the returned transaction rolls back to it.
+    pub async fn savepoint(&mut self) -> Result<Transaction<'_>, PgPoolError> {
+        Ok(Transaction::new(self.tx.transaction().await?))
+    }
+
+    /// Runs `f` inside a savepoint, releasing it on `Ok` and rolling back to it on `Err`.
+    /// The outer transaction stays usable either way.
+    pub async fn nested<T, F>(&mut self, f: F) -> Result<T, PgPoolError>
+    where
+        F: AsyncFnOnce(&mut Transaction<'_>) -> Result<T, PgPoolError>,
+    {
+        let tx = self.savepoint().await?;
+        scoped(tx, f).await
+    }
+}
+
+impl<'a> Deref for Transaction<'a> {
+    type Target = PoolTransaction<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+/// Runs `f` in a transaction, committing on `Ok` and rolling back on `Err`.
+/// On panic the dropped transaction is rolled back by the driver.
+pub(crate) async fn transaction<T, F>(
+    db: PgHandle<'_>,
+    opts: TxOptions,
+    mut f: F
+) -> Result<T, PgPoolError>
+where
+    F: AsyncFnMut(&mut Transaction<'_>) -> Result<T, PgPoolError>,
+{
+    let mut client = driver::get(db).await?;
+    let mut attempt = 0;
+    loop {
+        match run(&mut client, &opts, &mut f).await {
+            Err(e) if attempt < opts.retries && is_retryable(&e) => {
+                let wait = opts.backoff.saturating_mul(2u32.saturating_pow(attempt));
+                attempt += 1;
+                debug!("Retry transaction ({attempt}/{}) after {wait:?}: {e}", opts.retries);
+                tokio::time::sleep(wait).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn run<T, F>(
+    client: &mut Client,
+    opts: &TxOptions,
+    f: &mut F
+) -> Result<T, PgPoolError>
+where
+    F: AsyncFnMut(&mut Transaction<'_>) -> Result<T, PgPoolError>,
+{
+    let builder = client.build_transaction()
+        .read_only(opts.read_only)
+        .deferrable(opts.deferrable);
+    let builder = match opts.isolation_level {
+        Some(level) => builder.isolation_level(level),
+        None => builder
+    };
+    let tx = Transaction::new(builder.start().await?);
+    scoped(tx, f).await
+}
+
+async fn scoped<T, F>(mut tx: Transaction<'_>, f: F) -> Result<T, PgPoolError>
+where
+    F: AsyncFnOnce(&mut Transaction<'_>) -> Result<T, PgPoolError>,
+{
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await?;
+            Ok(value)
+        }
+        Err(e) => {
+            if let Err(rollback_err) = tx.rollback().await {
+                error!("Rollback failed: {rollback_err}");
+            }
+            Err(e)
+        }
+    }
+}
+
+fn is_retryable(e: &PgPoolError) -> bool {
+    matches!(e.code(),
+             Some(&SqlState::T_R_SERIALIZATION_FAILURE) |
+             Some(&SqlState::T_R_DEADLOCK_DETECTED))
+}
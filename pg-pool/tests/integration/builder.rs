@@ -0,0 +1,45 @@
+use pg_pool::{builder, pg, pgr};
+
+#[tokio::test]
+async fn select_binds_optional_filters() {
+    for (min, expected) in [(None, 10), (Some(4i32), 7)] {
+        let (sql, params) = builder::select("g", "generate_series(1, 10) g")
+            .where_opt("g >= ?", min.as_ref())
+            .and("g <> ?", &[&0i32])
+            .order_by("g")
+            .build();
+        let rows = pgr::query(&sql, &params).await.unwrap();
+        assert_eq!(rows.len(), expected);
+    }
+
+    let (sql, params) = builder::select("g", "generate_series(1, 10) g")
+        .order_by("g DESC")
+        .limit(2)
+        .offset(1)
+        .build();
+    let rows = pgr::query(&sql, &params).await.unwrap();
+    assert_eq!(rows.iter().map(|r| r.get::<_, i32>(0)).collect::<Vec<_>>(), [9, 8]);
+}
+
+#[tokio::test]
+async fn upsert_updates_on_conflict() {
+    pg::execute("CREATE TABLE IF NOT EXISTS builder_test (id int PRIMARY KEY, name text)", &[])
+        .await.unwrap();
+    pg::execute("DELETE FROM builder_test", &[]).await.unwrap();
+
+    let (sql, params) = builder::insert("builder_test").value("id", &1i32).value("name", &"a").build();
+    assert_eq!(pg::execute(&sql, &params).await.unwrap(), 1);
+
+    for name in ["b", "c"] {
+        let (sql, params) = builder::upsert("builder_test", &["id"])
+            .value("id", &1i32)
+            .value("name", &name)
+            .returning("name")
+            .build();
+        let row = pg::query_one(&sql, &params).await.unwrap();
+        assert_eq!(row.get::<_, String>(0), name);
+    }
+
+    let (sql, params) = builder::upsert("builder_test", &["id"]).value("id", &1i32).build();
+    assert_eq!(pg::execute(&sql, &params).await.unwrap(), 0);
+}
@@ -0,0 +1,85 @@
+use pg_pool::{ClusterOptions, PgCluster, PgExecutor, PgPool, TxOptions};
+use server_conf::{SV_CONF, DbConf};
+
+fn unreachable() -> DbConf {
+    DbConf { host: "127.0.0.1".to_string(), hosts: None, port: 1, ..SV_CONF.db.clone() }
+}
+
+async fn backend_pid<E: PgExecutor>(executor: &E) -> i32 {
+    executor.query_one("SELECT pg_backend_pid()", &[]).await.unwrap().get(0)
+}
+
+#[tokio::test]
+async fn cluster_owns_its_pools() {
+    let cluster = PgCluster::new(SV_CONF.db.clone(), vec![SV_CONF.db.clone()], ClusterOptions::default()).unwrap();
+    cluster.init().await.unwrap();
+    let status = cluster.status();
+    assert_eq!(status.writer.size, 1);
+    assert_eq!(status.readers.len(), 1);
+    assert_eq!(status.readers[0].size, 1);
+
+    // A drained cluster pool leaves the global pools alone
+    let held = cluster.writer().get().await.unwrap();
+    assert!(cluster.writer().query("SELECT 1", &[]).await.unwrap_err().is_timeout());
+    assert!(backend_pid(&PgPool::Writer).await > 0);
+    drop(held);
+
+    let row = cluster.reader().query_one("SELECT $1::int4", &[&5i32]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 5);
+    assert_eq!(cluster.writer().pool(), PgPool::Writer);
+    cluster.close();
+    assert!(cluster.writer().query("SELECT 1", &[]).await.is_err());
+}
+
+#[tokio::test]
+async fn cluster_reader_transactions_are_read_only() {
+    let cluster = PgCluster::new(SV_CONF.db.clone(), vec![SV_CONF.db.clone()], ClusterOptions::default()).unwrap();
+    let err = cluster.reader().transaction(TxOptions::default(), async |tx| {
+        tx.execute("CREATE TEMP TABLE cluster_read_only (id int)", &[]).await?;
+        Ok(())
+    }).await.unwrap_err();
+    assert_eq!(err.code().map(|c| c.code()), Some("25006"));
+
+    let n = cluster.writer().transaction(TxOptions::default(), async |tx| {
+        tx.execute("CREATE TEMP TABLE cluster_writable (id int)", &[]).await
+    }).await.unwrap();
+    assert_eq!(n, 0);
+}
+
+#[tokio::test]
+async fn cluster_skips_unreachable_readers() {
+    let cluster = PgCluster::new(
+        SV_CONF.db.clone(), vec![unreachable(), SV_CONF.db.clone()], ClusterOptions::default()
+    ).unwrap();
+    for _ in 0..3 {
+        let row = cluster.reader().query_one("SELECT 1::int4", &[]).await.unwrap();
+        assert_eq!(row.get::<_, i32>(0), 1);
+    }
+    assert_eq!(cluster.status().readers.len(), 2);
+}
+
+#[tokio::test]
+async fn cluster_falls_back_to_writer_when_configured() {
+    let strict = PgCluster::new(SV_CONF.db.clone(), vec![unreachable()], ClusterOptions::default()).unwrap();
+    assert!(strict.reader().query("SELECT 1", &[]).await.is_err());
+
+    let fallback = PgCluster::new(
+        SV_CONF.db.clone(), vec![unreachable()], ClusterOptions { fallback: true, ..Default::default() }
+    ).unwrap();
+    let row = fallback.reader().query_one("SELECT 1::int4", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 1);
+
+    let err = fallback.init().await.unwrap_err();
+    assert_eq!(err.pool(), PgPool::Reader);
+}
+
+#[tokio::test]
+async fn cluster_replaces_closed_idle_connections() {
+    let cluster = PgCluster::new(SV_CONF.db.clone(), vec![], ClusterOptions::default()).unwrap();
+    let pid = backend_pid(&cluster.writer()).await;
+    PgPool::Writer.execute("SELECT pg_terminate_backend($1)", &[&pid]).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // The dead session is noticed at checkout, before the statement is sent
+    assert_ne!(backend_pid(&cluster.writer()).await, pid);
+}
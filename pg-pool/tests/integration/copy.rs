@@ -0,0 +1,79 @@
+use futures_util::{StreamExt, TryStreamExt, stream};
+use pg_pool::{pg, pgr, CopyOptions, PgPoolError};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use tokio_postgres::types::ToSql;
+
+type Values = Vec<Box<dyn ToSql + Send + Sync>>;
+
+#[tokio::test]
+async fn copy_in_binary_rows() {
+    setup("copy_in_test").await;
+    let progress = Arc::new(Mutex::new(vec![]));
+    let reported = progress.clone();
+    let opts = CopyOptions {
+        on_progress: Some(Box::new(move |n| reported.lock().unwrap().push(n))),
+        progress_interval: 100,
+        ..Default::default()
+    };
+
+    let rows = stream::iter(0..250).map(|i| -> Values { vec![Box::new(i), Box::new(format!("name{i}"))] });
+    let count = pg::copy_in_with("copy_in_test", &["id", "name"], rows, opts).await.unwrap();
+    assert_eq!(count, 250);
+    assert_eq!(*progress.lock().unwrap(), vec![100, 200]);
+
+    let row = pg::query_one("SELECT count(*), max(name) FROM copy_in_test", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 250);
+    assert_eq!(row.get::<_, &str>(1), "name99");
+}
+
+#[tokio::test]
+async fn copy_in_cancel_rolls_back() {
+    setup("copy_cancel_test").await;
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    let opts = CopyOptions { cancel: Some(cancel.clone()), ..Default::default() };
+
+    let rows = stream::iter(0..100).map(move |i| -> Values {
+        if i == 50 { flag.store(true, Ordering::Relaxed); }
+        vec![Box::new(i), Box::new("x")]
+    });
+    let result = pg::copy_in_with("copy_cancel_test", &["id", "name"], rows, opts).await;
+    assert!(matches!(result, Err(PgPoolError::Cancelled)));
+
+    let row = pg::query_one("SELECT count(*) FROM copy_cancel_test", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 0);
+}
+
+#[tokio::test]
+async fn copy_out_binary_rows() {
+    let rows: Vec<_> = pgr::copy_out("SELECT g AS id, 'v' || g AS name FROM generate_series(1, 3) g").await.unwrap()
+        .try_collect().await.unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[2].get::<i32>(0), 3);
+    assert_eq!(rows[2].get::<&str>(1), "v3");
+}
+
+#[tokio::test]
+async fn copy_out_csv_bytes() {
+    let chunks: Vec<_> = pgr::copy_out_csv("SELECT g AS id FROM generate_series(1, 2) g").await.unwrap()
+        .try_collect().await.unwrap();
+    let csv: Vec<u8> = chunks.concat();
+    assert_eq!(String::from_utf8(csv).unwrap(), "id\n1\n2\n");
+}
+
+#[tokio::test]
+async fn copy_out_cancel() {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let opts = CopyOptions { cancel: Some(cancel.clone()), ..Default::default() };
+    let mut rows = pgr::copy_out_with("SELECT g FROM generate_series(1, 100000) g", opts).await.unwrap();
+
+    assert!(rows.next().await.unwrap().is_ok());
+    cancel.store(true, Ordering::Relaxed);
+    assert!(matches!(rows.next().await, Some(Err(PgPoolError::Cancelled))));
+    assert!(rows.next().await.is_none());
+}
+
+async fn setup(table: &str) {
+    pg::execute(&format!("CREATE TABLE IF NOT EXISTS {table} (id int, name text)"), &[]).await.unwrap();
+    pg::execute(&format!("TRUNCATE {table}"), &[]).await.unwrap();
+}
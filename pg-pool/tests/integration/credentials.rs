@@ -0,0 +1,52 @@
+use deadpool_postgres::Object;
+use pg_pool::{ClusterOptions, CredentialError, PasswordFn, PgCluster, create_pool};
+use server_conf::{SV_CONF, DbConf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn counting(calls: Arc<AtomicUsize>, fail: bool) -> PasswordFn<impl Fn(&DbConf) -> std::future::Ready<Result<String, CredentialError>>> {
+    PasswordFn(move |db: &DbConf| {
+        calls.fetch_add(1, Ordering::Relaxed);
+        std::future::ready(match fail {
+            true => Err("token service down".into()),
+            false => Ok(db.password.clone()),
+        })
+    })
+}
+
+#[tokio::test]
+async fn credentials_are_fetched_per_connection() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let options = ClusterOptions { credentials: Some(Arc::new(counting(calls.clone(), false))), ..Default::default() };
+    let cluster = PgCluster::new(SV_CONF.db.clone(), vec![], options).unwrap();
+    cluster.init().await.unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+
+    // Reused connections do not ask again, new ones do
+    cluster.writer().query("SELECT 1", &[]).await.unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    drop(Object::take(cluster.writer().get().await.unwrap()));
+    cluster.writer().query("SELECT 1", &[]).await.unwrap();
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn credentials_failure_keeps_last_password() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let options = ClusterOptions { credentials: Some(Arc::new(counting(calls.clone(), true))), ..Default::default() };
+    let cluster = PgCluster::new(SV_CONF.db.clone(), vec![], options).unwrap();
+    let row = cluster.writer().query_one("SELECT 1::int4", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 1);
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn credentials_read_password_file() {
+    let path = std::env::temp_dir().join(format!("pg_pool_it_password_{}", std::process::id()));
+    std::fs::write(&path, format!("{}\n", SV_CONF.db.password)).unwrap();
+    let db = DbConf { password: String::new(), password_file: Some(path.to_string_lossy().into()), ..SV_CONF.db.clone() };
+    let pool = create_pool(&db).unwrap();
+    let row = pool.get().await.unwrap().query_one("SELECT 1::int4", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 1);
+    std::fs::remove_file(&path).unwrap();
+}
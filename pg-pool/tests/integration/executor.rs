@@ -0,0 +1,36 @@
+use pg_pool::{pg, PgExecutor, PgPool, PgPoolError, TxOptions, Type};
+
+#[tokio::test]
+async fn executor_on_pools() {
+    assert_eq!(add_one(&PgPool::Writer, 1).await.unwrap(), 2);
+    assert_eq!(add_one(&PgPool::Reader, 2).await.unwrap(), 3);
+    assert_eq!(add_one_pp(&PgPool::Writer, 3).await.unwrap(), 4);
+    assert_eq!(add_one_pp(&PgPool::Reader, 4).await.unwrap(), 5);
+}
+
+#[tokio::test]
+async fn executor_on_client() {
+    let client = pg::get().await.unwrap();
+    assert_eq!(add_one(&client, 5).await.unwrap(), 6);
+    assert_eq!(add_one_pp(&client, 6).await.unwrap(), 7);
+}
+
+#[tokio::test]
+async fn executor_on_transaction() {
+    let result = pg::transaction(TxOptions::default(), async |tx| {
+        let a = add_one(tx, 7).await?;
+        let b = tx.nested(async |sp| add_one_pp(sp, a).await).await?;
+        Ok(b)
+    }).await;
+    assert_eq!(result.unwrap(), 9);
+}
+
+async fn add_one<E: PgExecutor>(db: &E, n: i64) -> Result<i64, PgPoolError> {
+    let row = db.query_one("SELECT 1 + $1::int8", &[&n]).await?;
+    Ok(row.get(0))
+}
+
+async fn add_one_pp<E: PgExecutor>(db: &E, n: i64) -> Result<i64, PgPoolError> {
+    let rows = db.query_pp("SELECT 1 + $1", &[Type::INT8], &[&n]).await?;
+    Ok(rows[0].get(0))
+}
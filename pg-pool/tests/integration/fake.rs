@@ -0,0 +1,61 @@
+use pg_pool::{PgExecutor, PgPoolError, Type, fake::{Executed, FakeExecutor, FakeRows}};
+use tokio_postgres::error::SqlState;
+
+#[tokio::test]
+async fn fake_returns_scripted_rows() {
+    let fake = FakeExecutor::new().await;
+    fake.push_rows(FakeRows::new(&[("id", Type::INT4), ("name", Type::TEXT)])
+                   .row(&[&1i32, &"alice"])
+                   .row(&[&2i32, &None::<&str>]));
+
+    let rows = fake.query("SELECT id, name FROM users WHERE id > $1", &[&0i32]).await.unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].get::<_, i32>("id"), 1);
+    assert_eq!(rows[0].get::<_, &str>("name"), "alice");
+    assert_eq!(rows[1].get::<_, Option<&str>>("name"), None);
+
+    assert_eq!(fake.executed(), vec![Executed {
+        sql: "SELECT id, name FROM users WHERE id > $1".into(),
+        params: vec!["0".into()],
+    }]);
+}
+
+#[tokio::test]
+async fn fake_returns_scripted_error() {
+    let fake = FakeExecutor::new().await;
+    fake.push_error(SqlState::UNIQUE_VIOLATION);
+
+    let result = fake.execute("INSERT INTO users (name) VALUES ($1)", &[&"bob"]).await;
+    let err = result.unwrap_err();
+    assert!(matches!(err, PgPoolError::Sql(_)));
+    assert_eq!(err.code(), Some(&SqlState::UNIQUE_VIOLATION));
+
+    assert_eq!(fake.executed()[0].params, vec!["\"bob\"".to_string()]);
+}
+
+#[tokio::test]
+async fn fake_records_statements_in_order() {
+    let fake = FakeExecutor::new().await;
+    fake.push_execute(3);
+
+    assert_eq!(fake.execute("UPDATE users SET active = $1", &[&true]).await.unwrap(), 3);
+    assert_eq!(fake.execute("DELETE FROM sessions", &[]).await.unwrap(), 0);
+    assert!(fake.query_opt("SELECT 1", &[]).await.unwrap().is_none());
+
+    assert_eq!(fake.sql(), vec![
+        "UPDATE users SET active = $1",
+        "DELETE FROM sessions",
+        "SELECT 1",
+    ]);
+}
+
+#[tokio::test]
+async fn fake_supports_prepared_variants() {
+    let fake = FakeExecutor::new().await;
+    fake.push_rows(FakeRows::new(&[("total", Type::INT8)]).row(&[&42i64]));
+
+    let row = fake.query_one_pp("SELECT count(*) FROM users WHERE org_id = $1", &[Type::INT8], &[&7i64])
+        .await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 42);
+    assert_eq!(fake.executed()[0].params, vec!["7".to_string()]);
+}
@@ -0,0 +1,99 @@
+use pg_pool::{FromRow, PgPoolError, pg, pgr};
+
+#[derive(Debug, PartialEq)]
+struct Level(u8);
+
+impl TryFrom<i32> for Level {
+    type Error = String;
+
+    fn try_from(v: i32) -> Result<Self, Self::Error> {
+        u8::try_from(v).map(Level).map_err(|_| format!("level {v} out of range"))
+    }
+}
+
+#[derive(Debug, PartialEq, FromRow)]
+struct Audit {
+    created_by: String,
+}
+
+#[derive(Debug, PartialEq, FromRow)]
+struct User {
+    id: i32,
+    #[pg(rename = "user_name")]
+    name: String,
+    #[pg(default)]
+    email: Option<String>,
+    #[pg(default)]
+    tags: Vec<String>,
+    #[pg(try_from = "i32")]
+    level: Level,
+    #[pg(flatten)]
+    audit: Audit,
+}
+
+const USER: &str = "SELECT 1 AS id, 'alice' AS user_name, 3 AS level, 'admin' AS created_by";
+
+#[tokio::test]
+async fn query_one_as_maps_fields() {
+    let user: User = pg::query_one_as(USER, &[]).await.unwrap();
+    assert_eq!(user, User {
+        id: 1,
+        name: "alice".to_string(),
+        email: None,
+        tags: vec![],
+        level: Level(3),
+        audit: Audit { created_by: "admin".to_string() },
+    });
+}
+
+#[tokio::test]
+async fn query_as_maps_rows() {
+    let audits = pgr::query_as::<Audit>(
+        "SELECT 'u' || g AS created_by FROM generate_series(1, 3) g", &[]
+    ).await.unwrap();
+    assert_eq!(audits.len(), 3);
+    assert_eq!(audits[2].created_by, "u3");
+
+    let none = pg::query_opt_as::<Audit>("SELECT 'x' AS created_by WHERE false", &[]).await.unwrap();
+    assert_eq!(none, None);
+}
+
+#[tokio::test]
+async fn default_replaces_null() {
+    let user = pgr::query_opt_as::<User>(
+        "SELECT 1 AS id, 'bob' AS user_name, NULL::text AS email, NULL::text[] AS tags, 0 AS level, 'x' AS created_by",
+        &[]
+    ).await.unwrap().unwrap();
+    assert_eq!(user.email, None);
+    assert!(user.tags.is_empty());
+}
+
+#[tokio::test]
+async fn missing_column_names_field() {
+    let err = pg::query_one_as::<User>("SELECT 1 AS id, 3 AS level, 'x' AS created_by", &[]).await.unwrap_err();
+    match &err {
+        PgPoolError::Column { field, column, .. } => {
+            assert_eq!(*field, "User.name");
+            assert_eq!(*column, "user_name");
+        }
+        e => panic!("unexpected error: {e}"),
+    }
+    assert!(err.to_string().contains("`User.name`"));
+}
+
+#[tokio::test]
+async fn type_mismatch_names_field() {
+    let err = pg::query_one_as::<User>(
+        "SELECT 'one' AS id, 'alice' AS user_name, 3 AS level, 'x' AS created_by", &[]
+    ).await.unwrap_err();
+    assert!(matches!(err, PgPoolError::Column { field: "User.id", column: "id", .. }), "{err}");
+}
+
+#[tokio::test]
+async fn try_from_failure_names_field() {
+    let err = pg::query_one_as::<User>(
+        "SELECT 1 AS id, 'alice' AS user_name, 300 AS level, 'x' AS created_by", &[]
+    ).await.unwrap_err();
+    assert!(matches!(err, PgPoolError::Column { field: "User.level", column: "level", .. }), "{err}");
+    assert!(err.to_string().contains("level 300 out of range"));
+}
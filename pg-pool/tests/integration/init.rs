@@ -0,0 +1,22 @@
+use pg_pool::{ClusterOptions, InitError, PgCluster, PgPool, status};
+use server_conf::{SV_CONF, DbConf};
+
+#[tokio::test]
+async fn init_warms_up_writer() {
+    pg_pool::init().await.unwrap();
+    assert!(status().writer.size >= 1);
+}
+
+#[test]
+fn init_error_names_pool() {
+    let err = InitError::Config { pool: PgPool::Reader, message: "bad host".to_string() };
+    assert_eq!(err.pool(), PgPool::Reader);
+    assert_eq!(err.to_string(), "pg reader pool config: bad host");
+}
+
+#[test]
+fn init_rejects_empty_pool() {
+    let empty = DbConf { pool_max: Some(0), ..SV_CONF.db.clone() };
+    let err = PgCluster::new(SV_CONF.db.clone(), vec![empty], ClusterOptions::default()).unwrap_err();
+    assert!(matches!(err, InitError::Config { pool: PgPool::Reader, .. }));
+}
@@ -0,0 +1,39 @@
+use futures_util::StreamExt;
+use pg_pool::{ListenEvent, listen, pg};
+use std::time::Duration;
+
+async fn next(listener: &mut pg_pool::Listener) -> ListenEvent {
+    tokio::time::timeout(Duration::from_secs(10), listener.next()).await.unwrap().unwrap()
+}
+
+#[tokio::test]
+async fn listen_receives_notifications() {
+    let mut listener = listen(&["listen_a", "listen_B"]).await.unwrap();
+    pg::execute("SELECT pg_notify('listen_a', 'one'), pg_notify('listen_B', 'two')", &[]).await.unwrap();
+
+    for (channel, payload) in [("listen_a", "one"), ("listen_B", "two")] {
+        match next(&mut listener).await {
+            ListenEvent::Notification(n) => {
+                assert_eq!(n.channel(), channel);
+                assert_eq!(n.payload(), payload);
+            }
+            e => panic!("unexpected event: {e:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn listen_reconnects_and_reports_gap() {
+    let mut listener = listen(&["listen_reconnect"]).await.unwrap();
+    let n = pg::execute(
+        "SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE query = 'LISTEN \"listen_reconnect\";'", &[]
+    ).await.unwrap();
+    assert_eq!(n, 1);
+    assert!(matches!(next(&mut listener).await, ListenEvent::Gap));
+
+    pg::execute("SELECT pg_notify('listen_reconnect', 'after')", &[]).await.unwrap();
+    match next(&mut listener).await {
+        ListenEvent::Notification(n) => assert_eq!(n.payload(), "after"),
+        e => panic!("unexpected event: {e:?}"),
+    }
+}
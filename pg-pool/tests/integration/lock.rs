@@ -0,0 +1,95 @@
+use pg_pool::{ClusterOptions, PgCluster, Pool, create_pool, lock_key, pg::{self, Client}};
+use server_conf::SV_CONF;
+use std::time::Duration;
+
+// A second instance competing for the same locks
+fn other() -> Pool {
+    create_pool(&SV_CONF.db).unwrap()
+}
+
+async fn try_lock(client: &Client, key: i64) -> bool {
+    client.query_one("SELECT pg_try_advisory_lock($1)", &[&key]).await.unwrap().get(0)
+}
+
+async fn eventually(f: impl AsyncFn() -> bool) {
+    for _ in 0..100 {
+        if f().await {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("condition not met");
+}
+
+#[tokio::test]
+async fn try_advisory_lock_excludes_other_sessions() {
+    let key = lock_key("lock_test_try");
+    let other = other().get().await.unwrap();
+
+    let lock = pg::try_advisory_lock(key).await.unwrap().unwrap();
+    assert_eq!(lock.key(), key);
+    assert!(!try_lock(&other, key).await);
+    lock.unlock().await.unwrap();
+    assert!(try_lock(&other, key).await);
+
+    // Held by `other` now; its connection keeps the lock
+    assert!(pg::try_advisory_lock(key).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn advisory_lock_waits_and_unlocks_on_drop() {
+    let key = lock_key("lock_test_wait");
+    let holder = other().get().await.unwrap();
+    holder.execute("SELECT pg_advisory_lock($1)", &[&key]).await.unwrap();
+
+    let waiter = tokio::spawn(async move { pg::advisory_lock(key).await.map(drop) });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!waiter.is_finished());
+    holder.execute("SELECT pg_advisory_unlock($1)", &[&key]).await.unwrap();
+    waiter.await.unwrap().unwrap();
+
+    eventually(async || try_lock(&holder, key).await).await;
+}
+
+#[tokio::test]
+async fn leader_takes_over_and_resigns() {
+    let key = lock_key("lock_test_leader");
+    let holder = other().get().await.unwrap();
+    holder.execute("SELECT pg_advisory_lock($1)", &[&key]).await.unwrap();
+
+    let leader = pg::leader(key, Duration::from_millis(50)).unwrap();
+    let mut watch = leader.watch();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(!leader.is_leader());
+
+    holder.execute("SELECT pg_advisory_unlock($1)", &[&key]).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), watch.wait_for(|l| *l)).await.unwrap().unwrap();
+
+    // Losing the connection drops leadership until it reconnects
+    holder.execute(
+        "SELECT pg_terminate_backend(pid) FROM pg_locks WHERE locktype = 'advisory' AND objid = ($1::bigint & 4294967295)::oid",
+        &[&key]
+    ).await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), watch.wait_for(|l| !*l)).await.unwrap().unwrap();
+    tokio::time::timeout(Duration::from_secs(5), watch.wait_for(|l| *l)).await.unwrap().unwrap();
+
+    drop(leader);
+    eventually(async || try_lock(&holder, key).await).await;
+}
+
+#[tokio::test]
+async fn cancelled_advisory_lock_is_not_taken_later() {
+    let key = lock_key("lock_test_cancel");
+    let holder = other().get().await.unwrap();
+    holder.execute("SELECT pg_advisory_lock($1)", &[&key]).await.unwrap();
+
+    let cluster = PgCluster::new(SV_CONF.db.clone(), vec![], ClusterOptions::default()).unwrap();
+    let waiting = tokio::time::timeout(Duration::from_millis(100), cluster.writer().advisory_lock(key)).await;
+    assert!(waiting.is_err());
+    holder.execute("SELECT pg_advisory_unlock($1)", &[&key]).await.unwrap();
+
+    // The abandoned session neither got the lock nor went back to the pool
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(try_lock(&holder, key).await);
+    assert_eq!(cluster.status().writer.size, 0);
+}
@@ -1,4 +1,23 @@
 extern crate pg_pool;
 
+mod builder;
+mod cluster;
+mod copy;
+mod credentials;
+mod executor;
+mod fake;
+mod init;
+mod from_row;
+mod listen;
+mod lock;
+mod metrics;
+mod migrate;
+mod named;
 mod pgpool;
 mod prepare;
+mod queue;
+mod quote;
+mod retry;
+mod stream;
+mod timeout;
+mod transaction;
@@ -0,0 +1,25 @@
+use pg_pool::{metrics, pg};
+
+#[tokio::test]
+async fn metrics_count_queries_and_errors() {
+    let before = metrics::metrics();
+    pg::query_one_pp("SELECT 1 + $1", &[], &[&1i32]).await.unwrap();
+    pg::query_one_pp("SELECT 1 + $1", &[], &[&1i32]).await.unwrap();
+    let err = pg::execute("SELECT * FROM metrics_test_missing", &[]).await.unwrap_err();
+    assert_eq!(err.code().map(|c| c.code()), Some("42P01"));
+
+    let after = metrics::metrics();
+    assert!(after.writer.query_latency.count >= before.writer.query_latency.count + 3);
+    assert!(after.writer.checkout_wait.count >= before.writer.checkout_wait.count + 3);
+    assert!(after.writer.errors.get("42P01").copied().unwrap_or(0) > before.writer.errors.get("42P01").copied().unwrap_or(0));
+    assert!(after.cache_hits > before.cache_hits);
+}
+
+#[tokio::test]
+async fn status_reports_pool_size() {
+    pg::query_one("SELECT 1", &[]).await.unwrap();
+    let status = pg_pool::status();
+    assert_eq!(status.writer.max, 1);
+    assert!(status.writer.size <= status.writer.max);
+    assert!(status.reader.is_none());
+}
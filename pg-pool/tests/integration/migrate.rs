@@ -0,0 +1,63 @@
+use pg_pool::{PgPoolError, embed_migrations, pg, migrate::{Migrator, State}};
+
+#[tokio::test]
+async fn migrator_applies_reverts_and_repairs() {
+    pg::execute("DROP TABLE IF EXISTS mig_test, mig_test_history", &[]).await.unwrap();
+    let migrator = Migrator::new(embed_migrations!("tests/migrations")).table("mig_test_history");
+    assert_eq!(migrator.migrations().len(), 2);
+
+    // Concurrent runs serialize on the advisory lock
+    let (a, b) = tokio::join!(migrator.up(), migrator.up());
+    let mut applied = [a.unwrap(), b.unwrap()].concat();
+    applied.sort();
+    assert_eq!(applied, [1, 2]);
+    assert!(migrator.up().await.unwrap().is_empty());
+    pg::execute("INSERT INTO mig_test (id, name) VALUES (1, 'a')", &[]).await.unwrap();
+
+    assert_eq!(migrator.down().await.unwrap(), Some(2));
+    let status = migrator.status().await.unwrap();
+    assert_eq!(status.iter().map(|s| s.state).collect::<Vec<_>>(), [State::Applied, State::Pending]);
+    assert_eq!(migrator.up().await.unwrap(), [2]);
+
+    pg::execute("UPDATE mig_test_history SET checksum = 'x' WHERE version = 1", &[]).await.unwrap();
+    assert!(matches!(migrator.up().await, Err(PgPoolError::Migration(_))));
+    assert_eq!(migrator.repair().await.unwrap(), [1]);
+    assert!(migrator.status().await.unwrap().iter().all(|s| s.state == State::Applied));
+}
+
+#[tokio::test]
+async fn migrator_status_does_not_write() {
+    pg::execute("DROP TABLE IF EXISTS mig_status_history", &[]).await.unwrap();
+    let migrator = Migrator::new(embed_migrations!("tests/migrations")).table("mig_status_history");
+    let status = migrator.status().await.unwrap();
+    assert!(status.iter().all(|s| s.state == State::Pending));
+    let exists: bool = pg::query_one("SELECT to_regclass('mig_status_history') IS NOT NULL", &[]).await.unwrap().get(0);
+    assert!(!exists);
+}
+
+#[tokio::test]
+async fn migrator_reads_dir_at_runtime() {
+    let migrator = Migrator::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/migrations")).unwrap();
+    let embedded = embed_migrations!("tests/migrations");
+    assert_eq!(migrator.migrations().len(), embedded.len());
+    for (a, b) in migrator.migrations().iter().zip(&embedded) {
+        assert_eq!(a.checksum(), b.checksum());
+        assert_eq!(a.down, b.down);
+    }
+}
+
+#[tokio::test]
+async fn migrator_rejects_duplicate_versions() {
+    let dir = std::env::temp_dir().join(format!("pg_pool_mig_dup_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("0001_a.sql"), "SELECT 1").unwrap();
+    std::fs::write(dir.join("0001_b.sql"), "SELECT 2").unwrap();
+    let err = Migrator::from_dir(&dir).err().unwrap();
+    assert!(matches!(err, PgPoolError::Migration(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let mut migrations = embed_migrations!("tests/migrations");
+    migrations.push(migrations[0].clone());
+    let err = Migrator::new(migrations).table("mig_dup_history").up().await.unwrap_err();
+    assert!(matches!(err, PgPoolError::Migration(_)));
+}
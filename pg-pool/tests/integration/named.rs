@@ -0,0 +1,59 @@
+use pg_pool::{NamedParams, PgPoolError, pg, pgr};
+use std::collections::HashMap;
+use tokio_postgres::types::ToSql;
+
+#[derive(NamedParams)]
+struct Page {
+    limit: i64,
+    #[pg(skip)]
+    _cursor: Option<String>,
+}
+
+#[derive(NamedParams)]
+struct Search {
+    #[pg(rename = "lo")]
+    from: i32,
+    to: i32,
+    #[pg(flatten)]
+    page: Page,
+}
+
+#[tokio::test]
+async fn query_named_binds_struct() {
+    let search = Search { from: 3, to: 10, page: Page { limit: 2, _cursor: None } };
+    let rows = pg::query_named(
+        "SELECT g FROM generate_series(:lo::int4, :to::int4) g WHERE g >= :lo ORDER BY g LIMIT :limit",
+        &search
+    ).await.unwrap();
+    let values: Vec<i32> = rows.iter().map(|r| r.get(0)).collect();
+    assert_eq!(values, [3, 4]);
+}
+
+#[tokio::test]
+async fn query_named_binds_map() {
+    let mut params: HashMap<&str, &(dyn ToSql + Sync)> = HashMap::new();
+    params.insert("a", &2i32);
+    params.insert("b", &"x");
+    let row = pgr::query_one_named("SELECT :a::int4 * :a::int4, :b::text || ':b'", &params).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 4);
+    assert_eq!(row.get::<_, String>(1), "x:b");
+
+    let row = pg::query_opt_named("SELECT 1 WHERE :n::int4 > 0", &[("n", &0i32 as &(dyn ToSql + Sync))])
+        .await.unwrap();
+    assert!(row.is_none());
+}
+
+#[tokio::test]
+async fn execute_named_runs_statement() {
+    let params: HashMap<String, Box<dyn ToSql + Send + Sync>> = HashMap::from([("v".to_string(), Box::new(7i32) as _)]);
+    let n = pg::execute_named("SELECT * FROM generate_series(1, :v)", &params).await.unwrap();
+    assert_eq!(n, 7);
+}
+
+#[tokio::test]
+async fn query_named_reports_missing_param() {
+    let params: HashMap<&str, &(dyn ToSql + Sync)> = HashMap::new();
+    let err = pg::query_named("SELECT :missing::int4", &params).await.unwrap_err();
+    assert!(matches!(err, PgPoolError::Param(_)));
+    assert!(err.to_string().contains(":missing"));
+}
@@ -1,4 +1,5 @@
-use pg_pool::{pg, pgr};
+use pg_pool::{pg, pgr, PgPoolError, try_get};
+use tokio_postgres::error::SqlState;
 
 #[tokio::test]
 async fn pg_query() {
@@ -27,3 +28,24 @@ async fn pgr_query_one() {
   let result: i32 = row.get(0);
   assert_eq!(result, 2i32);
 }
+
+#[tokio::test]
+async fn pg_query_sql_error() {
+  let result = pg::query("SELECT * FROM no_such_table", &[]).await;
+  let err = result.unwrap_err();
+  assert!(matches!(err, PgPoolError::Sql(_)));
+  assert_eq!(err.code(), Some(&SqlState::UNDEFINED_TABLE));
+}
+
+#[tokio::test]
+async fn pg_query_one_row_count_error() {
+  let result = pg::query_one("SELECT 1 WHERE false", &[]).await;
+  assert!(matches!(result.unwrap_err(), PgPoolError::Query(_)));
+}
+
+#[tokio::test]
+async fn pg_try_get_decode_error() {
+  let row = pg::query_one("SELECT 'text'::text", &[]).await.unwrap();
+  let result = try_get::<_, i32>(&row, 0);
+  assert!(matches!(result.unwrap_err(), PgPoolError::Decode(_)));
+}
@@ -5,6 +5,7 @@ use tokio_postgres::error::SqlState;
 async fn invalid_prepare_statement_pg() {
     let stmt = pg::prepare_typed_cached("SELECT 1 + $1", &[Type::INT8]).await.unwrap();
     let row = pg::query_one(&stmt, &[&8i64]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 9);
 
     invalidate_pg().await;
     let result = pg::query_one(&stmt, &[&9i64]).await;
@@ -42,6 +43,7 @@ async fn query_one_pp_recover_invalid_statement_pg() {
 async fn invalid_prepare_statement_pgr() {
     let stmt = pgr::prepare_typed_cached("SELECT 4 + $1", &[Type::INT8]).await.unwrap();
     let row = pgr::query_one(&stmt, &[&8i64]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 12);
 
     invalidate_pgr().await;
     let result = pgr::query_one(&stmt, &[&9i64]).await;
@@ -75,6 +77,47 @@ async fn query_one_pp_recover_invalid_statement_pgr() {
     assert_eq!(data, 15i64);
 }
 
+#[tokio::test]
+async fn query_opt_pp_recover_invalid_statement_pg() {
+    let _stmt = pg::prepare_typed_cached("SELECT 7 + $1", &[Type::INT8]).await.unwrap();
+
+    invalidate_pg().await;
+    let result = pg::query_opt_pp("SELECT 7 + $1", &[Type::INT8], &[&9i64]).await;
+    let data: i64 = result.unwrap().unwrap().get(0);
+    assert_eq!(data, 16i64);
+}
+
+#[tokio::test]
+async fn execute_pp_recover_invalid_statement_pg() {
+    let _stmt = pg::prepare_typed_cached("SELECT 8 + $1", &[Type::INT8]).await.unwrap();
+
+    invalidate_pg().await;
+    let result = pg::execute_pp("SELECT 8 + $1", &[Type::INT8], &[&9i64]).await;
+    assert_eq!(result.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn query_opt_pp_recover_invalid_statement_pgr() {
+    let _stmt = pgr::prepare_typed_cached("SELECT 9 + $1", &[Type::INT8]).await.unwrap();
+
+    invalidate_pgr().await;
+    let result = pgr::query_opt_pp("SELECT 9 + $1", &[Type::INT8], &[&9i64]).await;
+    let data: i64 = result.unwrap().unwrap().get(0);
+    assert_eq!(data, 18i64);
+}
+
+#[tokio::test]
+async fn query_pp_recover_changed_result_type_pg() {
+    pg::execute("CREATE TABLE IF NOT EXISTS plan_test (id int)", &[]).await.unwrap();
+    pg::execute("ALTER TABLE plan_test DROP COLUMN IF EXISTS extra", &[]).await.unwrap();
+    let rows = pg::query_pp("SELECT * FROM plan_test", &[], &[]).await.unwrap();
+    assert!(rows.is_empty());
+
+    pg::execute("ALTER TABLE plan_test ADD COLUMN extra int", &[]).await.unwrap();
+    let result = pg::query_pp("SELECT * FROM plan_test", &[], &[]).await;
+    assert!(result.is_ok());
+}
+
 async fn invalidate_pg() {
     pg::execute("DEALLOCATE ALL", &[]).await.unwrap();
 }
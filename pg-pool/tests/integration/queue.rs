@@ -0,0 +1,164 @@
+use pg_pool::{PgPool, PgPoolError, TxOptions, pg, queue::{self, Job, Queue}};
+use std::time::{Duration, SystemTime};
+
+async fn count(queue: &str) -> i64 {
+    pg::query_one("SELECT count(*) FROM pg_pool_jobs WHERE queue = $1", &[&queue]).await.unwrap().get(0)
+}
+
+async fn ok(_: Job) -> Result<(), String> {
+    Ok(())
+}
+
+#[tokio::test]
+async fn enqueue_follows_transaction() {
+    queue::install().await.unwrap();
+    let q = Queue::new("queue_test_tx");
+    pg::execute("DELETE FROM pg_pool_jobs WHERE queue = 'queue_test_tx'", &[]).await.unwrap();
+
+    let result: Result<(), PgPoolError> = pg::transaction(TxOptions::default(), async |tx| {
+        q.enqueue(tx, "rolled back").await?;
+        Err(PgPoolError::Cancelled)
+    }).await;
+    assert!(result.is_err());
+    assert_eq!(count("queue_test_tx").await, 0);
+
+    pg::transaction(TxOptions::default(), async |tx| q.enqueue(tx, "committed").await).await.unwrap();
+    let mut seen = Vec::new();
+    assert!(q.run_once(&mut |job: Job| {
+        seen.push(job.payload);
+        async { Ok::<_, String>(()) }
+    }).await.unwrap());
+    assert_eq!(seen, ["committed"]);
+    assert_eq!(count("queue_test_tx").await, 0);
+    assert!(!q.run_once(&mut ok).await.unwrap());
+}
+
+#[tokio::test]
+async fn failed_jobs_back_off_then_die() {
+    queue::install().await.unwrap();
+    let q = Queue::new("queue_test_fail").max_attempts(2).backoff(Duration::ZERO, Duration::ZERO);
+    pg::execute("DELETE FROM pg_pool_jobs WHERE queue = 'queue_test_fail'", &[]).await.unwrap();
+    let id = q.enqueue(&PgPool::Writer, "boom").await.unwrap();
+
+    let mut fail = |_| async { Err::<(), _>("handler failed") };
+    assert!(q.run_once(&mut fail).await.unwrap());
+    assert!(q.dead().await.unwrap().is_empty());
+    assert!(q.run_once(&mut fail).await.unwrap());
+    assert!(!q.run_once(&mut fail).await.unwrap());
+
+    let dead = q.dead().await.unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!(dead[0].id, id);
+    assert_eq!(dead[0].attempts, 2);
+    assert_eq!(dead[0].last_error.as_deref(), Some("handler failed"));
+
+    assert!(q.retry_dead(id).await.unwrap());
+    assert!(q.run_once(&mut ok).await.unwrap());
+    assert_eq!(count("queue_test_fail").await, 0);
+}
+
+#[tokio::test]
+async fn scheduled_and_leased_jobs_wait() {
+    queue::install().await.unwrap();
+    let q = Queue::new("queue_test_schedule").lease(Duration::ZERO);
+    pg::execute("DELETE FROM pg_pool_jobs WHERE queue = 'queue_test_schedule'", &[]).await.unwrap();
+
+    q.enqueue_at(&PgPool::Writer, "later", SystemTime::now() + Duration::from_secs(3600)).await.unwrap();
+    assert!(!q.run_once(&mut ok).await.unwrap());
+
+    // Once the lease expires another worker claims the job; the first
+    // worker's late failure must not put it back
+    q.enqueue(&PgPool::Writer, "now").await.unwrap();
+    let reclaimed = q.run_once(&mut |_| {
+        let q = q.clone();
+        async move {
+            let mut attempts = 0;
+            q.run_once(&mut |job: Job| {
+                attempts = job.attempts;
+                async { Ok::<_, String>(()) }
+            }).await.unwrap();
+            Err(attempts)
+        }
+    }).await;
+    assert!(reclaimed.unwrap());
+    assert_eq!(count("queue_test_schedule").await, 1);
+    let row = pg::query_one("SELECT payload FROM pg_pool_jobs WHERE queue = 'queue_test_schedule'", &[]).await.unwrap();
+    assert_eq!(row.get::<_, String>(0), "later");
+}
+
+#[tokio::test]
+async fn late_success_keeps_reclaimed_job() {
+    queue::install().await.unwrap();
+    let q = Queue::new("queue_test_late").lease(Duration::ZERO);
+    pg::execute("DELETE FROM pg_pool_jobs WHERE queue = 'queue_test_late'", &[]).await.unwrap();
+    q.enqueue(&PgPool::Writer, "job").await.unwrap();
+
+    // The first claim finishes while the second one is still running
+    let (claimed_tx, claimed_rx) = tokio::sync::oneshot::channel();
+    let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+    let mut signal = Some((claimed_tx, release_rx));
+    let mut second = None;
+    let mut first = Some(claimed_rx);
+    assert!(q.run_once(&mut |_| {
+        let q = q.clone();
+        let mut signal = signal.take();
+        second = Some(tokio::spawn(async move {
+            q.run_once(&mut |job: Job| {
+                let (claimed, release) = signal.take().unwrap();
+                claimed.send(job.attempts).unwrap();
+                async move { release.await.map_err(|e| e.to_string()) }
+            }).await.unwrap()
+        }));
+        let claimed = first.take().unwrap();
+        async move {
+            assert_eq!(claimed.await.unwrap(), 2);
+            Ok::<_, String>(())
+        }
+    }).await.unwrap());
+    assert_eq!(count("queue_test_late").await, 1);
+
+    release_tx.send(()).unwrap();
+    assert!(second.unwrap().await.unwrap());
+    assert_eq!(count("queue_test_late").await, 0);
+}
+
+#[tokio::test]
+async fn expired_jobs_without_attempts_die() {
+    queue::install().await.unwrap();
+    let q = Queue::new("queue_test_expired").max_attempts(2);
+    pg::execute("DELETE FROM pg_pool_jobs WHERE queue = 'queue_test_expired'", &[]).await.unwrap();
+    let id = q.enqueue(&PgPool::Writer, "crashing").await.unwrap();
+
+    // Its worker died during the last attempt
+    pg::execute(
+        "UPDATE pg_pool_jobs SET status = 'running', attempts = 2, locked_until = now() - interval '1 second' WHERE id = $1",
+        &[&id]
+    ).await.unwrap();
+    assert!(!q.run_once(&mut ok).await.unwrap());
+    let dead = q.dead().await.unwrap();
+    assert_eq!(dead.len(), 1);
+    assert_eq!((dead[0].id, dead[0].attempts), (id, 2));
+    assert_eq!(dead[0].last_error.as_deref(), Some("lease expired"));
+}
+
+#[tokio::test]
+async fn run_wakes_on_notify() {
+    queue::install().await.unwrap();
+    let q = Queue::new("queue_test_notify").poll_interval(Duration::from_secs(60));
+    pg::execute("DELETE FROM pg_pool_jobs WHERE queue = 'queue_test_notify'", &[]).await.unwrap();
+
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let worker = q.clone();
+    let handle = tokio::spawn(async move {
+        worker.run(|job: Job| {
+            let sent = tx.send(job.payload).map_err(|e| e.to_string());
+            async { sent }
+        }).await
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    q.enqueue(&PgPool::Writer, "wake").await.unwrap();
+    let payload = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap();
+    assert_eq!(payload.as_deref(), Some("wake"));
+    handle.abort();
+}
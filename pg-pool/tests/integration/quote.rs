@@ -0,0 +1,40 @@
+use pg_pool::{pg, pgr, quote_ident, quote_literal, quote_literals, text_array};
+
+const NASTY: &str = "it's a \\'; DROP TABLE t; --";
+
+#[tokio::test]
+async fn quote_literal_round_trips() {
+    let row = pg::query_one(&format!("SELECT {}::text", quote_literal(NASTY)), &[]).await.unwrap();
+    assert_eq!(row.get::<_, String>(0), NASTY);
+
+    let row = pg::query_one(&format!("SELECT 1 AS {}", quote_ident("we\"ird")), &[]).await.unwrap();
+    assert_eq!(row.columns()[0].name(), "we\"ird");
+}
+
+#[tokio::test]
+async fn quote_literals_builds_in_list() {
+    let values = ["a", NASTY];
+    let query = format!("SELECT count(*) FROM (VALUES ('a'), ($1)) v(x) WHERE x IN ({})", quote_literals(&values));
+    let row = pgr::query_one(&query, &[&NASTY]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 2);
+}
+
+#[tokio::test]
+async fn slices_bind_as_any_arrays() {
+    let ids: &[i32] = &[2, 4, 9];
+    let rows = pg::query("SELECT g FROM generate_series(1, 5) g WHERE g = ANY($1)", &[&ids]).await.unwrap();
+    assert_eq!(rows.len(), 2);
+
+    let names = text_array(&[1, 3]);
+    let row = pgr::query_one("SELECT count(*) FROM generate_series(1, 5) g WHERE g::text = ANY($1::text[])", &[&names])
+        .await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 2);
+}
+
+#[tokio::test]
+#[allow(deprecated)]
+async fn vec2string_escapes_quotes() {
+    let values: Box<[&str]> = Box::new([NASTY]);
+    let row = pg::query_one(&format!("SELECT {}::text", pg::vec2string(&values)), &[]).await.unwrap();
+    assert_eq!(row.get::<_, String>(0), NASTY);
+}
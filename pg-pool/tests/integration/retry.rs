@@ -0,0 +1,69 @@
+use pg_pool::{pg, pgr, PgPoolError, RetryPolicy, retry};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio_postgres::error::SqlState;
+
+#[tokio::test]
+async fn retry_transient_error() {
+    let attempts = AtomicU32::new(0);
+    let result = retry(&policy(), || async {
+        if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+            pg::execute(RAISE_ADMIN_SHUTDOWN, &[]).await?;
+        }
+        pg::query_one("SELECT 1 + $1", &[&1i32]).await
+    }).await;
+    assert_eq!(result.unwrap().get::<_, i32>(0), 2);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn retry_gives_up_after_max_attempts() {
+    let attempts = AtomicU32::new(0);
+    let result: Result<u64, PgPoolError> = retry(&policy(), || async {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        pg::execute(RAISE_ADMIN_SHUTDOWN, &[]).await
+    }).await;
+    assert_eq!(result.unwrap_err().code(), Some(&SqlState::ADMIN_SHUTDOWN));
+    assert_eq!(attempts.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn retry_skips_permanent_error() {
+    let attempts = AtomicU32::new(0);
+    let result = retry(&policy(), || async {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        pg::query("SELECT * FROM no_such_table", &[]).await
+    }).await;
+    assert_eq!(result.unwrap_err().code(), Some(&SqlState::UNDEFINED_TABLE));
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn pgr_retries_transient_error() {
+    pg::execute("CREATE SEQUENCE IF NOT EXISTS retry_flaky_seq", &[]).await.unwrap();
+    pg::execute(FLAKY, &[]).await.unwrap();
+
+    // Reads retry on their own, writes only when wrapped in `retry`
+    pg::execute("ALTER SEQUENCE retry_flaky_seq RESTART", &[]).await.unwrap();
+    let row = pgr::query_one("SELECT retry_flaky()", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 2);
+
+    pg::execute("ALTER SEQUENCE retry_flaky_seq RESTART", &[]).await.unwrap();
+    let err = pg::query_one("SELECT retry_flaky()", &[]).await.unwrap_err();
+    assert_eq!(err.code(), Some(&SqlState::ADMIN_SHUTDOWN));
+}
+
+fn policy() -> RetryPolicy {
+    RetryPolicy { max_attempts: 3, backoff: Duration::from_millis(1), ..RetryPolicy::writer() }
+}
+
+const RAISE_ADMIN_SHUTDOWN: &str =
+    "DO $$ BEGIN RAISE EXCEPTION 'shutdown' USING ERRCODE = '57P01'; END $$";
+
+// Fails with a transient error on the first call after a restart of `retry_flaky_seq`
+const FLAKY: &str = "CREATE OR REPLACE FUNCTION retry_flaky() RETURNS bigint AS $$
+    DECLARE n bigint := nextval('retry_flaky_seq');
+    BEGIN
+        IF n = 1 THEN RAISE EXCEPTION 'shutdown' USING ERRCODE = '57P01'; END IF;
+        RETURN n;
+    END $$ LANGUAGE plpgsql";
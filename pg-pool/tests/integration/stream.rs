@@ -0,0 +1,51 @@
+use futures_util::{StreamExt, TryStreamExt};
+use pg_pool::{pg, pgr, Type};
+use tokio_postgres::error::SqlState;
+
+#[tokio::test]
+async fn pg_query_stream_fetches_in_batches() {
+    let stream = pg::query_stream("SELECT g FROM generate_series(1, $1) g", &[Type::INT4], &[&2500i32], 1000)
+        .await.unwrap();
+    let rows: Vec<i32> = stream.map_ok(|row| row.get::<_, i32>(0)).try_collect().await.unwrap();
+    assert_eq!(rows.len(), 2500);
+    assert_eq!(rows[2499], 2500);
+}
+
+#[tokio::test]
+async fn pgr_query_stream_fetches_in_batches() {
+    let stream = pgr::query_stream("SELECT g FROM generate_series(1, 10) g", &[], &[], 3).await.unwrap();
+    let rows: Vec<i32> = stream.map_ok(|row| row.get::<_, i32>(0)).try_collect().await.unwrap();
+    assert_eq!(rows, (1..=10).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn query_stream_releases_client_on_drop() {
+    for _ in 0..3 {
+        let mut stream = pg::query_stream("SELECT g FROM generate_series(1, 100) g", &[], &[], 10)
+            .await.unwrap();
+        let row = stream.next().await.unwrap().unwrap();
+        assert_eq!(row.get::<_, i32>(0), 1);
+    }
+
+    let row = pg::query_one("SELECT count(*) FROM pg_cursors WHERE name = 'pg_pool_stream'", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 0);
+}
+
+#[tokio::test]
+async fn query_stream_reports_sql_error() {
+    let result = pg::query_stream("SELECT * FROM no_such_table", &[], &[], 10).await;
+    assert_eq!(result.err().unwrap().code(), Some(&SqlState::UNDEFINED_TABLE));
+
+    let row = pg::query_one("SELECT 1 + $1", &[&1i32]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 2);
+}
+
+#[tokio::test]
+async fn query_stream_recovers_invalid_statement() {
+    let stream = pg::query_stream("SELECT 1", &[], &[], 10).await.unwrap();
+    assert_eq!(stream.count().await, 1);
+
+    pg::execute("DEALLOCATE ALL", &[]).await.unwrap();
+    let stream = pg::query_stream("SELECT 1", &[], &[], 10).await.unwrap();
+    assert_eq!(stream.count().await, 1);
+}
@@ -0,0 +1,42 @@
+use pg_pool::{pg, pgr};
+use std::time::{Duration, Instant};
+
+async fn running(marker: &str) -> i64 {
+    pg::query_one(
+        "SELECT count(*) FROM pg_stat_activity
+            WHERE state = 'active' AND query LIKE '%' || $1 || '%' AND query NOT LIKE '%pg_stat_activity%'",
+        &[&marker]
+    ).await.unwrap().get(0)
+}
+
+#[tokio::test]
+async fn timeout_cancels_query_on_server() {
+    let start = Instant::now();
+    let err = pg::query_timeout("SELECT pg_sleep(10) /* timeout_test_cancel */", &[], Duration::from_millis(200))
+        .await.unwrap_err();
+    assert!(err.is_query_timeout(), "{err}");
+    assert!(!err.is_timeout());
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let mut left = 1;
+    for _ in 0..20 {
+        left = running("timeout_test_cancel").await;
+        if left == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(left, 0);
+
+    // The pool keeps working
+    let row = pg::query_one_timeout("SELECT 1", &[], Duration::from_secs(5)).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 1);
+}
+
+#[tokio::test]
+async fn timeout_leaves_fast_queries_alone() {
+    let n = pg::execute_timeout("SELECT pg_sleep(0.01)", &[], Duration::from_secs(5)).await.unwrap();
+    assert_eq!(n, 1);
+    let row = pgr::query_opt_timeout("SELECT $1::int4", &[&7i32], Duration::from_secs(5)).await.unwrap();
+    assert_eq!(row.unwrap().get::<_, i32>(0), 7);
+}
@@ -0,0 +1,155 @@
+use pg_pool::{pg, pgr, IsolationLevel, PgPoolError, Transaction, TxOptions};
+use std::time::Duration;
+use tokio_postgres::error::SqlState;
+
+#[tokio::test]
+async fn transaction_commits_on_ok() {
+    setup().await;
+    pg::execute("DELETE FROM tx_test WHERE name = 'commit'", &[]).await.unwrap();
+
+    let id: i32 = pg::transaction(TxOptions::default(), async |tx| {
+        let row = tx.query_one("INSERT INTO tx_test (name) VALUES ('commit') RETURNING id", &[]).await?;
+        Ok(row.get(0))
+    }).await.unwrap();
+
+    let row = pg::query_one("SELECT id FROM tx_test WHERE name = 'commit'", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), id);
+}
+
+#[tokio::test]
+async fn transaction_rolls_back_on_err() {
+    setup().await;
+    pg::execute("DELETE FROM tx_test WHERE name = 'rollback'", &[]).await.unwrap();
+
+    let result: Result<(), _> = pg::transaction(TxOptions::default(), async |tx| {
+        tx.execute("INSERT INTO tx_test (name) VALUES ('rollback')", &[]).await?;
+        tx.execute("SELECT * FROM no_such_table", &[]).await?;
+        Ok(())
+    }).await;
+    assert_eq!(result.unwrap_err().code(), Some(&SqlState::UNDEFINED_TABLE));
+
+    let row = pg::query_opt("SELECT id FROM tx_test WHERE name = 'rollback'", &[]).await.unwrap();
+    assert!(row.is_none());
+}
+
+#[tokio::test]
+async fn transaction_retries_serialization_failure() {
+    let opts = TxOptions {
+        isolation_level: Some(IsolationLevel::Serializable),
+        retries: 2,
+        backoff: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let mut attempts = 0;
+    let result = pg::transaction(opts, async |tx| {
+        attempts += 1;
+        if attempts == 1 {
+            tx.batch_execute(RAISE_SERIALIZATION_FAILURE).await?;
+        }
+        Ok(attempts)
+    }).await;
+    assert_eq!(result.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn transaction_gives_up_after_retries() {
+    let opts = TxOptions { retries: 1, backoff: Duration::from_millis(1), ..Default::default() };
+    let mut attempts = 0;
+    let result: Result<(), PgPoolError> = pg::transaction(opts, async |tx| {
+        attempts += 1;
+        tx.batch_execute(RAISE_SERIALIZATION_FAILURE).await?;
+        Ok(())
+    }).await;
+    assert_eq!(result.unwrap_err().code(), Some(&SqlState::T_R_SERIALIZATION_FAILURE));
+    assert_eq!(attempts, 2);
+}
+
+#[tokio::test]
+async fn transaction_is_send() {
+    let handle = tokio::spawn(pg::transaction(TxOptions::default(), async |tx| {
+        let row = tx.query_one("SELECT 1 + $1", &[&1i32]).await?;
+        Ok(row.get::<_, i32>(0))
+    }));
+    assert_eq!(handle.await.unwrap().unwrap(), 2);
+}
+
+#[tokio::test]
+async fn pgr_transaction_is_read_only() {
+    setup().await;
+    let result: Result<(), _> = pgr::transaction(TxOptions::default(), async |tx| {
+        tx.execute("INSERT INTO tx_test (name) VALUES ('read_only')", &[]).await?;
+        Ok(())
+    }).await;
+    assert_eq!(result.unwrap_err().code(), Some(&SqlState::READ_ONLY_SQL_TRANSACTION));
+}
+
+const RAISE_SERIALIZATION_FAILURE: &str =
+    "DO $$ BEGIN RAISE EXCEPTION 'conflict' USING ERRCODE = '40001'; END $$";
+
+async fn setup() {
+    pg::execute("CREATE TABLE IF NOT EXISTS tx_test (id serial PRIMARY KEY, name text NOT NULL)", &[])
+        .await.unwrap();
+}
+
+#[tokio::test]
+async fn nested_rolls_back_to_savepoint() {
+    setup().await;
+    pg::execute("DELETE FROM tx_test WHERE name IN ('outer', 'inner')", &[]).await.unwrap();
+
+    pg::transaction(TxOptions::default(), async |tx| {
+        tx.execute("INSERT INTO tx_test (name) VALUES ('outer')", &[]).await?;
+        let inner: Result<(), _> = tx.nested(async |sp| {
+            sp.execute("INSERT INTO tx_test (name) VALUES ('inner')", &[]).await?;
+            sp.execute("SELECT * FROM no_such_table", &[]).await?;
+            Ok(())
+        }).await;
+        assert!(inner.is_err());
+        Ok(())
+    }).await.unwrap();
+
+    assert_eq!(count_names(&["outer"]).await, 1);
+    assert_eq!(count_names(&["inner"]).await, 0);
+}
+
+#[tokio::test]
+async fn nested_composes_helpers() {
+    setup().await;
+    pg::execute("DELETE FROM tx_test WHERE name IN ('helper1', 'helper2')", &[]).await.unwrap();
+
+    pg::transaction(TxOptions::default(), async |tx| {
+        insert_name(tx, "helper1").await?;
+        tx.nested(async |sp| insert_name(sp, "helper2").await).await
+    }).await.unwrap();
+
+    assert_eq!(count_names(&["helper1", "helper2"]).await, 2);
+}
+
+#[tokio::test]
+async fn savepoint_rolls_back_on_drop() {
+    setup().await;
+    pg::execute("DELETE FROM tx_test WHERE name IN ('kept', 'dropped')", &[]).await.unwrap();
+
+    pg::transaction(TxOptions::default(), async |tx| {
+        tx.execute("INSERT INTO tx_test (name) VALUES ('kept')", &[]).await?;
+        {
+            let sp = tx.savepoint().await?;
+            sp.execute("INSERT INTO tx_test (name) VALUES ('dropped')", &[]).await?;
+        }
+        Ok(())
+    }).await.unwrap();
+
+    assert_eq!(count_names(&["kept"]).await, 1);
+    assert_eq!(count_names(&["dropped"]).await, 0);
+}
+
+async fn insert_name(tx: &mut Transaction<'_>, name: &str) -> Result<(), PgPoolError> {
+    tx.nested(async |sp| {
+        sp.execute("INSERT INTO tx_test (name) VALUES ($1)", &[&name]).await?;
+        Ok(())
+    }).await
+}
+
+async fn count_names(names: &[&str]) -> i64 {
+    pg::query_one("SELECT count(*) FROM tx_test WHERE name = ANY($1)", &[&names]).await
+        .unwrap().get(0)
+}
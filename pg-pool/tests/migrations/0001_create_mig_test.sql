@@ -0,0 +1 @@
+CREATE TABLE mig_test (id int PRIMARY KEY);
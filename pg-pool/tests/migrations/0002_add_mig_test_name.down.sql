@@ -0,0 +1 @@
+ALTER TABLE mig_test DROP COLUMN name;
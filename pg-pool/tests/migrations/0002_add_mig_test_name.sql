@@ -0,0 +1 @@
+ALTER TABLE mig_test ADD COLUMN name text;
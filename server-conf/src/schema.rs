@@ -65,9 +65,18 @@ pub struct DbConf {
     pub port: u16,
     pub user: String,
     pub password: String,
+    pub password_file: Option<String>, // Re-read for new connections when it changes; overrides `password`
     pub pool_max: Option<usize>,  // Max size of connection pool
+    pub min_idle: Option<usize>,  // Connections opened by `pg_pool::init`, 1 by default
     pub timeout: Option<u64>,     // Timeout in millisec for getting connection pool
-    pub fallback: bool
+    pub query_timeout: Option<u64>, // Default timeout in millisec for queries
+    pub fallback: bool,
+    pub retry_max: Option<u32>,   // Max attempts on transient errors
+    pub retry_backoff: Option<u64>, // Base backoff in millisec between attempts
+    pub retry_max_backoff: Option<u64>, // Cap on the backoff in millisec, 5000 by default
+    pub slow_query: Option<u64>,  // Log statements slower than this, in millisec
+    pub slow_query_sample: Option<f64>, // Share of slow statements logged, 0.0 to 1.0
+    pub slow_query_redact: Option<Vec<String>>, // Columns whose values are hidden; all by default
 }
 
 impl Default for DbConf {
@@ -79,9 +88,18 @@ impl Default for DbConf {
             port: 5432,
             user: "".into(),
             password: "".into(),
+            password_file: None,
             pool_max: None,
+            min_idle: None,
             timeout: None,
-            fallback: false
+            query_timeout: None,
+            fallback: false,
+            retry_max: None,
+            retry_backoff: None,
+            retry_max_backoff: None,
+            slow_query: None,
+            slow_query_sample: None,
+            slow_query_redact: None,
         }
     }
 }
@@ -0,0 +1,292 @@
+//! Transactional email outbox.
+//!
+//! `enqueue` stores a message through any `PgExecutor`, so it is only sent
+//! if the surrounding transaction commits. A `Dispatcher` drains the outbox
+//! through `SMTP_MAILER`. It claims a message for a lease and commits before
+//! sending, so no transaction stays open during SMTP and racing dispatchers
+//! never pick the same message, then records the outcome. Delivery is
+//! at-least-once: a message whose outcome was never recorded, e.g. after a
+//! crash, is sent again once its lease expires, while it has attempts left.
+//! Every attempt and its SMTP response is recorded in `smtp_outbox_attempts`.
+use lettre::{
+    Address, AsyncSmtpTransport, Message, Tokio1Executor,
+    address::Envelope,
+    transport::{smtp::{self, response::Response}, stub},
+};
+use log::{debug, warn};
+use pg_pool::{PgExecutor, PgPoolError, TxOptions, lock_key, pg};
+use smtp_pool::{AsyncTransport, SMTP_MAILER};
+use std::fmt::Display;
+use std::time::Duration;
+
+const TABLE: &str = "smtp_outbox";
+
+/// Creates the outbox tables if they do not exist.
+pub async fn install() -> Result<(), PgPoolError> {
+    let lock = pg::advisory_lock(lock_key(TABLE)).await?;
+    lock.client().batch_execute(&format!("
+        CREATE TABLE IF NOT EXISTS {TABLE} (
+            id bigserial PRIMARY KEY,
+            sender text,
+            recipients text[] NOT NULL,
+            message bytea NOT NULL,
+            status text NOT NULL DEFAULT 'pending',
+            attempts int NOT NULL DEFAULT 0,
+            next_attempt_at timestamptz NOT NULL DEFAULT now(),
+            last_response text,
+            created_at timestamptz NOT NULL DEFAULT now(),
+            sent_at timestamptz
+        );
+        CREATE INDEX IF NOT EXISTS {TABLE}_due ON {TABLE} (next_attempt_at) WHERE status IN ('pending', 'sending');
+        CREATE TABLE IF NOT EXISTS {TABLE}_attempts (
+            outbox_id bigint NOT NULL REFERENCES {TABLE} ON DELETE CASCADE,
+            attempt int NOT NULL,
+            ok boolean NOT NULL,
+            response text NOT NULL,
+            attempted_at timestamptz NOT NULL DEFAULT now(),
+            PRIMARY KEY (outbox_id, attempt)
+        );
+    ")).await?;
+    lock.unlock().await
+}
+
+/// Stores `message` for delivery. Returns its outbox id.
+pub async fn enqueue<E: PgExecutor>(executor: &E, message: &Message) -> Result<i64, PgPoolError> {
+    let envelope = message.envelope();
+    let sender = envelope.from().map(Address::to_string);
+    let recipients = envelope.to().iter().map(Address::to_string).collect::<Vec<_>>();
+    let row = executor.query_one(
+        &format!("INSERT INTO {TABLE} (sender, recipients, message) VALUES ($1, $2, $3) RETURNING id"),
+        &[&sender, &recipients, &message.formatted()]
+    ).await?;
+    Ok(row.get(0))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Pending,
+    /// Claimed by a dispatcher until its lease expires.
+    Sending,
+    Sent,
+    /// Permanently rejected or out of attempts.
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: i64,
+    pub status: Status,
+    pub attempts: i32,
+    pub last_response: Option<String>,
+}
+
+pub async fn status(id: i64) -> Result<Option<Entry>, PgPoolError> {
+    let row = pg::query_opt(
+        &format!("SELECT status, attempts, last_response FROM {TABLE} WHERE id = $1"), &[&id]
+    ).await?;
+    Ok(row.map(|row| Entry {
+        id,
+        status: match row.get::<_, &str>(0) {
+            "sending" => Status::Sending,
+            "sent" => Status::Sent,
+            "failed" => Status::Failed,
+            _ => Status::Pending,
+        },
+        attempts: row.get(1),
+        last_response: row.get(2),
+    }))
+}
+
+/// Text recorded for a successful send.
+pub trait Reply {
+    fn reply(&self) -> String;
+}
+
+impl Reply for Response {
+    fn reply(&self) -> String {
+        format!("{} {}", self.code(), self.message().collect::<Vec<_>>().join(" "))
+    }
+}
+
+impl Reply for () {
+    fn reply(&self) -> String {
+        "ok".to_string()
+    }
+}
+
+/// Send failure. Permanent failures are not retried.
+pub trait SendError: Display {
+    fn is_permanent(&self) -> bool {
+        false
+    }
+}
+
+impl SendError for smtp::Error {
+    fn is_permanent(&self) -> bool {
+        smtp::Error::is_permanent(self)
+    }
+}
+
+impl SendError for stub::Error {}
+
+pub struct Dispatcher<T> {
+    transport: T,
+    max_attempts: i32,
+    backoff: Duration,
+    max_backoff: Duration,
+    poll_interval: Duration,
+    lease: Duration,
+}
+
+impl Dispatcher<AsyncSmtpTransport<Tokio1Executor>> {
+    pub fn new() -> Self {
+        Self::with_transport(SMTP_MAILER.clone())
+    }
+}
+
+impl Default for Dispatcher<AsyncSmtpTransport<Tokio1Executor>> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Dispatcher<T>
+where
+    T: AsyncTransport + Sync,
+    T::Ok: Reply + 'static,
+    T::Error: SendError,
+{
+    pub fn with_transport(transport: T) -> Self {
+        Self {
+            transport,
+            max_attempts: 5,
+            backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(3600),
+            poll_interval: Duration::from_secs(5),
+            lease: Duration::from_secs(300),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Attempts before a message is marked failed. Default 5.
+    pub fn max_attempts(mut self, n: i32) -> Self {
+        self.max_attempts = n.max(1);
+        self
+    }
+
+    /// First retry delay, doubled per attempt up to `max`. Default 30s up to 1h.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.backoff = base;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Wait between polls when the outbox is empty. Default 5s.
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// How long a claimed message is left to this dispatcher before another
+    /// one may send it again. Keep it above the SMTP timeouts. Default 5m.
+    pub fn lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Sends one due message. Returns false when none was due.
+    pub async fn dispatch_once(&self) -> Result<bool, PgPoolError> {
+        // Messages whose dispatcher died during the last attempt are failed, not re-sent
+        let Some(row) = pg::query_opt(
+            &format!("WITH expired AS (
+                    UPDATE {TABLE} SET status = 'failed', last_response = 'lease expired'
+                    WHERE status = 'sending' AND next_attempt_at <= now() AND attempts >= $2
+                )
+                UPDATE {TABLE} SET status = 'sending', attempts = attempts + 1,
+                    next_attempt_at = now() + make_interval(secs => $1)
+                WHERE id = (
+                    SELECT id FROM {TABLE}
+                    WHERE (status = 'pending' OR (status = 'sending' AND attempts < $2))
+                        AND next_attempt_at <= now()
+                    ORDER BY next_attempt_at, id
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED)
+                RETURNING id, sender, recipients, message, attempts"),
+            &[&self.lease.as_secs_f64(), &self.max_attempts]
+        ).await? else {
+            return Ok(false);
+        };
+        let id: i64 = row.get(0);
+        let attempt: i32 = row.get(4);
+
+        let result = match envelope(row.get(1), row.get(2)) {
+            Ok(envelope) => match smtp_pool::send_with(&self.transport, &envelope, row.get(3)).await {
+                Ok(reply) => Ok(reply.reply()),
+                Err(e) => Err((e.to_string(), e.is_permanent())),
+            },
+            Err(e) => Err((e, true)),
+        };
+        let (status, ok, response, wait) = match result {
+            Ok(reply) => {
+                debug!("Outbox message {id} sent: {reply}");
+                ("sent", true, reply, Duration::ZERO)
+            }
+            Err((e, permanent)) if permanent || attempt >= self.max_attempts => {
+                warn!("Outbox message {id} failed after {attempt} attempts: {e}");
+                ("failed", false, e, Duration::ZERO)
+            }
+            Err((e, _)) => {
+                let wait = self.backoff
+                    .saturating_mul(2u32.saturating_pow(attempt as u32 - 1))
+                    .min(self.max_backoff);
+                debug!("Outbox message {id} failed, retry in {wait:?}: {e}");
+                ("pending", false, e, wait)
+            }
+        };
+
+        pg::transaction(TxOptions::default(), async |tx| {
+            // Another dispatcher owns the message once the lease expired
+            let owned = tx.execute(
+                &format!("UPDATE {TABLE} SET status = $2, last_response = $4,
+                    next_attempt_at = now() + make_interval(secs => $5),
+                    sent_at = CASE WHEN $2 = 'sent' THEN now() END
+                    WHERE id = $1 AND status = 'sending' AND attempts = $3"),
+                &[&id, &status, &attempt, &response, &wait.as_secs_f64()]
+            ).await?;
+            if owned == 0 {
+                warn!("Outbox message {id} lease expired during attempt {attempt}");
+                return Ok(());
+            }
+            tx.execute(
+                &format!("INSERT INTO {TABLE}_attempts (outbox_id, attempt, ok, response) VALUES ($1, $2, $3, $4)"),
+                &[&id, &attempt, &ok, &response]
+            ).await?;
+            Ok(())
+        }).await?;
+        Ok(true)
+    }
+
+    /// Drains the outbox until the future is dropped.
+    pub async fn run(&self) {
+        loop {
+            match self.dispatch_once().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => warn!("Outbox dispatch failed: {e}"),
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+}
+
+fn envelope(sender: Option<&str>, recipients: Vec<&str>) -> Result<Envelope, String> {
+    let sender = sender.map(str::parse::<Address>).transpose().map_err(|e| e.to_string())?;
+    let recipients = recipients.into_iter()
+        .map(str::parse::<Address>)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Envelope::new(sender, recipients).map_err(|e| e.to_string())
+}
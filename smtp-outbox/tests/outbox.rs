@@ -0,0 +1,104 @@
+use lettre::{Message, transport::stub::AsyncStubTransport};
+use pg_pool::{PgPool, PgPoolError, TxOptions, create_pool, pg};
+use server_conf::SV_CONF;
+use smtp_outbox::{Dispatcher, Status};
+use std::time::Duration;
+
+fn message(subject: &str) -> Message {
+    Message::builder()
+        .from("sender@example.com".parse().unwrap())
+        .to("rcpt@example.com".parse().unwrap())
+        .subject(subject)
+        .body(String::from("Hello"))
+        .unwrap()
+}
+
+async fn attempts(id: i64) -> Vec<(bool, String)> {
+    pg::query("SELECT ok, response FROM smtp_outbox_attempts WHERE outbox_id = $1 ORDER BY attempt", &[&id])
+        .await.unwrap()
+        .iter().map(|r| (r.get(0), r.get(1))).collect()
+}
+
+// Dispatchers drain the whole outbox, so the scenarios run in sequence
+#[tokio::test]
+async fn outbox_delivers_committed_messages_once() {
+    smtp_outbox::install().await.unwrap();
+    pg::execute("DELETE FROM smtp_outbox", &[]).await.unwrap();
+
+    let result: Result<(), PgPoolError> = pg::transaction(TxOptions::default(), async |tx| {
+        smtp_outbox::enqueue(tx, &message("rolled back")).await?;
+        Err(PgPoolError::Cancelled)
+    }).await;
+    assert!(result.is_err());
+    let count: i64 = pg::query_one("SELECT count(*) FROM smtp_outbox", &[]).await.unwrap().get(0);
+    assert_eq!(count, 0);
+
+    // Delivered once
+    let id = pg::transaction(TxOptions::default(), async |tx| {
+        smtp_outbox::enqueue(tx, &message("committed")).await
+    }).await.unwrap();
+    let ok = Dispatcher::with_transport(AsyncStubTransport::new_ok());
+    let sends = smtp_pool::metrics::metrics().sends;
+    assert!(ok.dispatch_once().await.unwrap());
+    assert_eq!(smtp_pool::metrics::metrics().sends, sends + 1);
+    assert!(!ok.dispatch_once().await.unwrap());
+    let sent = ok.transport().messages().await;
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].1.contains("Subject: committed"));
+    assert_eq!(sent[0].0.to()[0].to_string(), "rcpt@example.com");
+    let entry = smtp_outbox::status(id).await.unwrap().unwrap();
+    assert_eq!(entry.status, Status::Sent);
+    assert_eq!(attempts(id).await, [(true, "ok".to_string())]);
+
+    // A message locked by another dispatcher is skipped
+    let id = smtp_outbox::enqueue(&PgPool::Writer, &message("locked")).await.unwrap();
+    let other = create_pool(&SV_CONF.db).unwrap();
+    let mut client = other.get().await.unwrap();
+    let tx = client.transaction().await.unwrap();
+    tx.execute("SELECT 1 FROM smtp_outbox WHERE id = $1 FOR UPDATE", &[&id]).await.unwrap();
+    assert!(!ok.dispatch_once().await.unwrap());
+    tx.rollback().await.unwrap();
+    assert!(ok.dispatch_once().await.unwrap());
+    assert_eq!(ok.transport().messages().await.len(), 2);
+
+    // A claimed message is left alone until its lease expires, then sent again
+    let id = smtp_outbox::enqueue(&PgPool::Writer, &message("abandoned")).await.unwrap();
+    pg::execute(
+        "UPDATE smtp_outbox SET status = 'sending', attempts = 1, next_attempt_at = now() + interval '1 hour' WHERE id = $1",
+        &[&id]
+    ).await.unwrap();
+    assert_eq!(smtp_outbox::status(id).await.unwrap().unwrap().status, Status::Sending);
+    assert!(!ok.dispatch_once().await.unwrap());
+    pg::execute("UPDATE smtp_outbox SET next_attempt_at = now() WHERE id = $1", &[&id]).await.unwrap();
+    assert!(ok.dispatch_once().await.unwrap());
+    let entry = smtp_outbox::status(id).await.unwrap().unwrap();
+    assert_eq!((entry.status, entry.attempts), (Status::Sent, 2));
+    assert_eq!(ok.transport().messages().await.len(), 3);
+
+    // Unless it used up its attempts
+    let id = smtp_outbox::enqueue(&PgPool::Writer, &message("crashing")).await.unwrap();
+    pg::execute(
+        "UPDATE smtp_outbox SET status = 'sending', attempts = 5, next_attempt_at = now() WHERE id = $1",
+        &[&id]
+    ).await.unwrap();
+    assert!(!ok.dispatch_once().await.unwrap());
+    let entry = smtp_outbox::status(id).await.unwrap().unwrap();
+    assert_eq!((entry.status, entry.attempts), (Status::Failed, 5));
+    assert_eq!(entry.last_response.as_deref(), Some("lease expired"));
+    assert_eq!(ok.transport().messages().await.len(), 3);
+
+    // Failures are retried, then marked failed
+    let id = smtp_outbox::enqueue(&PgPool::Writer, &message("failing")).await.unwrap();
+    let failing = Dispatcher::with_transport(AsyncStubTransport::new_error())
+        .max_attempts(2)
+        .backoff(Duration::ZERO, Duration::ZERO);
+    assert!(failing.dispatch_once().await.unwrap());
+    let entry = smtp_outbox::status(id).await.unwrap().unwrap();
+    assert_eq!((entry.status, entry.attempts), (Status::Pending, 1));
+    assert!(entry.last_response.is_some());
+    assert!(failing.dispatch_once().await.unwrap());
+    let entry = smtp_outbox::status(id).await.unwrap().unwrap();
+    assert_eq!((entry.status, entry.attempts), (Status::Failed, 2));
+    assert!(!failing.dispatch_once().await.unwrap());
+    assert_eq!(attempts(id).await.iter().filter(|(ok, _)| !ok).count(), 2);
+}
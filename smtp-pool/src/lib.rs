@@ -1,24 +1,75 @@
 use lettre::{
+    address::Envelope,
     transport::smtp::{
         self,
         authentication::Credentials,
         client::{Certificate, Identity, Tls, TlsParameters},
         extension::ClientId,
+        response::Response,
         PoolConfig, SUBMISSIONS_PORT,
     },
-    AsyncSmtpTransport, Tokio1Executor,
+    AsyncSmtpTransport, Message, Tokio1Executor,
 };
 pub use lettre::AsyncTransport;
+pub use metrics::status;
 use log::error;
 use server_conf::{SV_CONF, MailConf};
 use std::{fs, sync::LazyLock};
 
+pub mod metrics;
+
 pub static SMTP_MAILER: LazyLock<AsyncSmtpTransport<Tokio1Executor>> = LazyLock::new(|| {
     let mail_conf = SV_CONF.mail.as_ref()
         .expect("mail config is required");
     mailer(mail_conf).unwrap()
 });
 
+/// Sends `message` through `SMTP_MAILER`, counted in `metrics` and traced
+/// with the `tracing` feature.
+pub async fn send(message: Message) -> Result<Response, smtp::Error> {
+    let envelope = message.envelope().clone();
+    send_raw(&envelope, &message.formatted()).await
+}
+
+/// Sends a formatted message through `SMTP_MAILER`, like `send`.
+pub async fn send_raw(envelope: &Envelope, email: &[u8]) -> Result<Response, smtp::Error> {
+    send_with(&*SMTP_MAILER, envelope, email).await
+}
+
+/// Sends a formatted message through `transport`, counted and traced like `send`.
+pub async fn send_with<T>(transport: &T, envelope: &Envelope, email: &[u8]) -> Result<T::Ok, T::Error>
+where
+    T: AsyncTransport + Sync,
+    T::Ok: 'static,
+{
+    let sending = metrics::Sending::start();
+    let result = traced_send(transport, envelope, email).await;
+    sending.finish(result.is_ok());
+    result
+}
+
+async fn traced_send<T>(transport: &T, envelope: &Envelope, email: &[u8]) -> Result<T::Ok, T::Error>
+where
+    T: AsyncTransport + Sync,
+    T::Ok: 'static,
+{
+    #[cfg(feature = "tracing")]
+    {
+        use tracing::Instrument;
+        let span = tracing::info_span!("smtp.send",
+            smtp.host = SV_CONF.mail.as_ref().map(|m| m.api_host.as_str()),
+            smtp.recipients = envelope.to().len(),
+            smtp.code = tracing::field::Empty);
+        let result = transport.send_raw(envelope, email).instrument(span.clone()).await;
+        if let Some(response) = result.as_ref().ok().and_then(|r| (r as &dyn std::any::Any).downcast_ref::<Response>()) {
+            span.record("smtp.code", response.code().to_string());
+        }
+        result
+    }
+    #[cfg(not(feature = "tracing"))]
+    transport.send_raw(envelope, email).await
+}
+
 fn mailer(mail_conf: &MailConf) -> Result<AsyncSmtpTransport<Tokio1Executor>, smtp::Error> {
     let tls_params = tls_builder(mail_conf)?;
 
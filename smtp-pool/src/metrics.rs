@@ -0,0 +1,82 @@
+//! Send counters. Only sends through `send`, `send_raw` and `send_with`
+//! are counted. lettre does not expose its connection pool, so the status
+//! reports sends in flight against the configured maximum.
+use server_conf::SV_CONF;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// lettre's default `PoolConfig::max_size`
+const DEFAULT_POOL_MAX: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub in_flight: u64,
+    pub max: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub sends: u64,
+    pub failures: u64,
+}
+
+pub fn status() -> Status {
+    Status {
+        in_flight: IN_FLIGHT.load(Ordering::Relaxed),
+        max: SV_CONF.mail.as_ref().and_then(|m| m.pool_max).unwrap_or(DEFAULT_POOL_MAX),
+    }
+}
+
+pub fn metrics() -> Metrics {
+    Metrics {
+        sends: SENDS.load(Ordering::Relaxed),
+        failures: FAILURES.load(Ordering::Relaxed),
+    }
+}
+
+static IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+static SENDS: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a send in flight until dropped.
+pub(crate) struct Sending;
+
+impl Sending {
+    pub fn start() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        Sending
+    }
+
+    pub fn finish(self, ok: bool) {
+        SENDS.fetch_add(1, Ordering::Relaxed);
+        if !ok {
+            FAILURES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Sending {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Status and metrics in Prometheus text exposition format.
+#[cfg(feature = "prometheus")]
+pub fn prometheus() -> String {
+    let status = status();
+    let metrics = metrics();
+    format!("\
+# HELP smtp_pool_in_flight Sends in progress.
+# TYPE smtp_pool_in_flight gauge
+smtp_pool_in_flight {}
+# HELP smtp_pool_max_size Maximum SMTP connections.
+# TYPE smtp_pool_max_size gauge
+smtp_pool_max_size {}
+# HELP smtp_pool_sends_total Messages sent or attempted.
+# TYPE smtp_pool_sends_total counter
+smtp_pool_sends_total {}
+# HELP smtp_pool_failures_total Failed sends.
+# TYPE smtp_pool_failures_total counter
+smtp_pool_failures_total {}
+", status.in_flight, status.max, metrics.sends, metrics.failures)
+}